@@ -3,71 +3,143 @@
 #![no_main]
 #![no_std]
 
+use app_common::{log, request_boot_after_drain};
+use boot_protocol::{BootRequest, BootTarget, MagicStore};
+#[cfg(not(feature = "panic-log"))]
 use panic_halt as _;
+use stm32f4xx_hal::{pac::USART2, serial::Serial};
 
-/// Jumps to another application via bootloader
+// Image header read by the bootloader, see boot_protocol::SlotHeader
+boot_protocol::slot_header!();
+
+/// Where the boot magic goes: the shared noinit word (matches bootloader
+/// noinit section)...
+#[cfg(not(feature = "rtc-backup"))]
+fn magic_store() -> impl MagicStore {
+    boot_protocol::NoinitStore::shared()
+}
+
+/// ...or RTC_BKP0R, unlocked during init
+#[cfg(feature = "rtc-backup")]
+fn magic_store() -> impl MagicStore {
+    unsafe { boot_protocol::RtcBackupStore::new() }
+}
+
+/// Switches to `target` via the bootloader
 ///
-/// # Safety
-/// Triggers a system reset after writing magic value to RAM
-pub unsafe fn jump_to_other(_addr: u32) -> ! {
-    use core::ptr::write_volatile;
+/// Lets `uart` finish sending whatever was logged before resetting. While
+/// switching is locked (the `lock` command) it only logs that it ignored the
+/// request.
+#[cfg(not(feature = "no-switch"))]
+pub fn switch_to(uart: &mut Serial<USART2>, target: BootTarget) {
+    if app_common::switching_locked() {
+        log!(uart, "APP1: Switching locked, ignoring");
+        return;
+    }
+    log!(uart, "APP1: Switching to {:?}...", target);
+    // Straight into the other app where possible, interrupt handlers (the
+    // UART command) still go through the reset
+    #[cfg(feature = "debug-jump")]
+    if app_common::in_thread_mode() {
+        app_common::drain(uart);
+        unsafe { app_common::debug_jump(target) }
+    }
+    request_boot_after_drain(&mut magic_store(), target, uart);
+}
 
-    // Magic RAM location and value for App2 (matches bootloader noinit section)
-    const MAGIC_ADDR: *mut u32 = 0x2001_FFF8 as *mut u32;
-    const MAGIC_APP2: u32 = 0xCAFE_BABE;
+/// Pinned build: the bootloader would ignore the request anyway
+#[cfg(feature = "no-switch")]
+pub fn switch_to(uart: &mut Serial<USART2>, _target: BootTarget) {
+    log!(uart, "APP1: Switching is disabled in this build");
+}
 
-    // Write magic value to RAM
-    write_volatile(MAGIC_ADDR, MAGIC_APP2);
+/// Switches to the next slot holding an app
+///
+/// With no other app installed there is nothing to switch to: logs that and
+/// returns.
+pub fn switch_to_next(uart: &mut Serial<USART2>) {
+    match app_common::next_slot(app_common::current_slot()) {
+        Some(target) => switch_to(uart, target),
+        None => log!(uart, "APP1: No other app installed, staying put"),
+    }
+}
 
-    // Memory barrier
-    cortex_m::asm::dsb();
+/// Resets and comes back to this app, once `uart` has drained
+pub fn restart(uart: &mut Serial<USART2>) -> ! {
+    app_common::restart_self(&mut magic_store(), uart)
+}
 
-    // Trigger system reset using cortex-m API
-    cortex_m::peripheral::SCB::sys_reset();
+/// Resets into the bootloader's update mode, once `uart` has drained
+pub fn reboot_to_update(uart: &mut Serial<USART2>) -> ! {
+    request_boot_after_drain(&mut magic_store(), BootRequest::UpdateMode, uart);
 }
 #[rtic::app(device = stm32f4xx_hal::pac, peripherals = true)]
 mod app {
 
-    use core::fmt::Write;
     use stm32f4xx_hal::{
-        gpio::{self, Edge, Input, Output, PushPull},
-        pac::{TIM1, USART2},
+        gpio::{self, Input, Output, PushPull},
+        pac::USART2,
         prelude::*,
-        rcc::Config,
-        serial::{config::Config as SerialConfig, Serial},
-        timer,
+        serial::{config::Config as SerialConfig, Event, Serial},
     };
 
-    use crate::jump_to_other;
+    use crate::{reboot_to_update, restart, switch_to_next};
+    use app_common::{log, AppDelay, AppSwitcher, Command, CommandParser};
 
-    const APP2_ADDR: u32 = 0x08024000; // App2 new address after bootloader
+    // Granularity of the long pause, keeps idle responsive between chunks
+    const PAUSE_CHUNK_MS: u32 = 50;
+
+    // How often idle samples the button while it waits
+    const TICK_MS: u32 = 10;
+
+    // Every step of `wait` feeds the watchdog, the long pause included
+    const _: () = assert!(TICK_MS <= app_common::MAX_FEED_INTERVAL_MS);
 
     // Resources shared between tasks
     #[shared]
     struct Shared {
         delayval: u32,
+        uart: Serial<USART2>,
     }
 
     // Local resources to specific tasks (cannot be shared)
     #[local]
     struct Local {
-        button: gpio::PA0<Input>,
+        switcher: AppSwitcher<gpio::PA0<Input>>,
         led: gpio::PC13<Output<PushPull>>,
-        delay: timer::DelayMs<TIM1>,
-        uart: Serial<USART2>,
+        delay: AppDelay,
+        parser: CommandParser,
     }
 
     #[init]
-    fn init(ctx: init::Context) -> (Shared, Local) {
+    fn init(mut ctx: init::Context) -> (Shared, Local) {
+        // Before anything deep runs; interrupts are still off during init
+        #[cfg(feature = "stack-paint")]
+        unsafe {
+            app_common::paint_stack();
+        }
+
         let mut dp = ctx.device;
 
+        // Same priorities as app2, also when entered by a debug jump that
+        // left the other app's in place
+        app_common::configure_nvic(&mut ctx.core.NVIC);
+
+        // Cycle counter for delay_us and the panic log's uptime stamps
+        app_common::enable_cycle_counter(&mut ctx.core.DCB, &mut ctx.core.DWT);
+
         // Configure and obtain handle for delay abstraction
-        // 1) Promote RCC structure to HAL to be able to configure clocks
-        let rcc = dp.RCC.constrain();
-        let mut rcc = rcc.freeze(Config::hse(25.MHz()));
+        // 1) Start the crystal and set up the clock tree (see HSE_HZ)
+        let mut rcc = app_common::init_clocks(dp.RCC);
+
+        // 2) Unlock the backup domain so the boot magic can be stored there
+        #[cfg(feature = "rtc-backup")]
+        unsafe {
+            boot_protocol::enable_backup_access();
+        }
 
         // 3) Create delay handle
-        let delay = dp.TIM1.delay_ms(&mut rcc);
+        let delay = app_common::init_delay(dp.TIM2, &mut rcc);
 
         // Configure the LED pin as a push pull ouput and obtain handle
         // On the Blackpill STM32F411CEU6 there is an on-board LED connected to pin PC13
@@ -81,8 +153,6 @@ mod app {
         // On the Blackpill STM32F411CEU6 there is a button connected to pin PA0
         // 1) Promote the GPIOA PAC struct
         let gpioa: gpio::gpioa::Parts = dp.GPIOA.split(&mut rcc);
-        // 2) Configure Pin and Obtain Handle
-        let mut button = gpioa.pa0.into_pull_up_input();
 
         // Configure UART2 for logging (PA2=TX, PA3=RX)
         let tx_pin = gpioa.pa2.into_alternate();
@@ -90,71 +160,187 @@ mod app {
         let mut uart = Serial::new(
             dp.USART2,
             (tx_pin, rx_pin),
-            SerialConfig::default().baudrate(115200.bps()),
+            SerialConfig::default().baudrate(app_common::CONSOLE_BAUD.bps()),
             &mut rcc,
         )
         .unwrap();
-        writeln!(uart, "\r\n=== APP1 STARTING ===").ok();
+        log!(uart, "\r\n=== APP1 STARTING ===");
+        log!(uart, "APP1: clocked from {}", app_common::clock_source());
 
-        // Configure Button Pin for Interrupts
+        // Configure the button pin
         // 1) Promote SYSCFG structure to HAL to be able to configure interrupts
         let mut syscfg = dp.SYSCFG.constrain(&mut rcc);
-        // 2) Make button an interrupt source
-        button.make_interrupt_source(&mut syscfg);
-        // 3) Configure the interruption to be triggered on a rising edge
-        button.trigger_on_edge(&mut dp.EXTI, Edge::Rising);
-        // 4) Enable gpio interrupt for button
-        button.enable_interrupt(&mut dp.EXTI);
+        // 2) Set the pull and press edge (shared with app2). EXTI0 isn't bound,
+        //    so the line never interrupts: idle polls the button through a
+        //    debouncing switcher instead
+        let button = app_common::configure_button(gpioa.pa0, &mut syscfg, &mut dp.EXTI);
+        let switcher = AppSwitcher::new(button);
 
-        writeln!(uart, "APP1: Init complete - button interrupt enabled").ok();
-        writeln!(uart, "APP1: Press button to switch to APP2").ok();
+        // Receive framed commands (see app_core::command) on the same UART
+        uart.listen(Event::RxNotEmpty);
+
+        log!(uart, "APP1: Init complete - button polled in idle");
+        log!(uart, "APP1: Press button to switch to APP2");
 
         (
             // Initialization of shared resources
-            Shared { delayval: 2000_u32 },
+            Shared {
+                delayval: 2000_u32,
+                uart,
+            },
             // Initialization of task local resources
             Local {
-                button,
+                switcher,
                 led,
                 delay,
-                uart,
+                parser: CommandParser::new(),
             },
         )
     }
 
     // Background task, runs whenever no other tasks are running
-    #[idle(local = [led, delay], shared = [delayval])]
+    #[idle(local = [led, delay, switcher], shared = [delayval, uart])]
     fn idle(mut ctx: idle::Context) -> ! {
         let led = ctx.local.led;
         let delay = ctx.local.delay;
+        let switcher = ctx.local.switcher;
+        let mut now_ms: u32 = 0;
+
+        // Sleeps `ms` in TICK_MS steps, switching apps on a button press
+        let mut wait = |ms: u32| {
+            let mut left = ms;
+            while left > 0 {
+                let step = left.min(TICK_MS);
+                delay.delay_ms(step);
+                now_ms = now_ms.wrapping_add(step);
+                left -= step;
+                // Tells the bootloader this image made it into its loop
+                app_common::heartbeat_tick();
+                app_common::feed_watchdog();
+
+                // Boot whichever other app is installed, or say there is none
+                if switcher.poll_press(now_ms) {
+                    ctx.shared.uart.lock(|uart| {
+                        log!(uart, "APP1: Button pressed!");
+                        switch_to_next(uart);
+                    });
+                }
+            }
+        };
+
         loop {
             // First fast blink
             led.set_high();
-            delay.delay_ms(150u32);
+            wait(150);
             led.set_low();
-            delay.delay_ms(50u32);
+            wait(50);
 
             // Second fast blink
             led.set_high();
-            delay.delay_ms(150u32);
+            wait(150);
             led.set_low();
-            delay.delay_ms(50u32);
+            wait(50);
 
-            // Long pause with LED ON
+            // Long pause with LED ON, split into short chunks so pending work
+            // (e.g. a new delay value) is picked up without waiting a full cycle
             led.set_high();
-            delay.delay_ms(ctx.shared.delayval.lock(|del| *del));
+            let mut elapsed = 0;
+            loop {
+                let pause = ctx.shared.delayval.lock(|del| *del);
+                if elapsed >= pause {
+                    break;
+                }
+                let chunk = (pause - elapsed).min(PAUSE_CHUNK_MS);
+                wait(chunk);
+                elapsed += chunk;
+            }
             led.set_low();
         }
     }
 
-    #[task(binds = EXTI0, local = [button, uart], shared=[delayval])]
-    fn gpio_interrupt_handler(ctx: gpio_interrupt_handler::Context) {
-        writeln!(ctx.local.uart, "APP1: Button pressed! Switching to APP2...").ok();
-        ctx.local.button.clear_interrupt_pending_bit();
+    // priority has to match app_common::USART2_PRIORITY
+    #[task(binds = USART2, priority = 1, local = [parser], shared = [uart, delayval])]
+    fn uart_rx_handler(mut ctx: uart_rx_handler::Context) {
+        let byte = ctx.shared.uart.lock(|uart| uart.read().ok());
+        let Some(command) = byte.and_then(|byte| ctx.local.parser.feed(byte)) else {
+            return;
+        };
 
-        // Jump to the other application
-        unsafe {
-            jump_to_other(APP2_ADDR);
+        match command {
+            Command::SetDelay(ms) => {
+                ctx.shared.delayval.lock(|del| *del = ms);
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP1: Long pause set to {} ms", ms);
+                });
+            }
+            Command::SwitchApp => {
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP1: Switch requested!");
+                    // Boot whichever other app is installed
+                    switch_to_next(uart);
+                });
+            }
+            Command::Status => {
+                let delay = ctx.shared.delayval.lock(|del| *del);
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP1: running, long pause {} ms", delay);
+                });
+            }
+            Command::ResetCause => {
+                let cause = app_common::last_reset_cause();
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP1: last reset: {}", cause);
+                });
+            }
+            Command::UpdateMode => {
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP1: Rebooting into update mode...");
+                    reboot_to_update(uart);
+                });
+            }
+            Command::Restart => {
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP1: Restarting...");
+                    restart(uart);
+                });
+            }
+            Command::Lock => {
+                app_common::lock_switching();
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP1: Switching locked");
+                });
+            }
+            Command::Unlock => {
+                app_common::unlock_switching();
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP1: Switching unlocked");
+                });
+            }
+            Command::StackUsage => {
+                ctx.shared.uart.lock(|uart| {
+                    #[cfg(feature = "stack-paint")]
+                    log!(
+                        uart,
+                        "APP1: stack high water {} of {} bytes",
+                        app_common::stack_high_water(),
+                        app_common::stack_size()
+                    );
+                    #[cfg(not(feature = "stack-paint"))]
+                    log!(uart, "APP1: stack not painted, build with stack-paint");
+                });
+            }
+            Command::Panics => {
+                ctx.shared.uart.lock(|uart| {
+                    let mut count = 0;
+                    for record in app_common::PanicLog::new().iter() {
+                        log!(uart, "APP1: panic {}", record);
+                        count += 1;
+                    }
+                    if count == 0 {
+                        log!(uart, "APP1: no panics recorded");
+                    }
+                });
+            }
         }
     }
 }
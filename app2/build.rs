@@ -1,31 +1,25 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
-//! For many projects this is optional, as the linker always searches the
-//! project root directory -- wherever `Cargo.toml` is. However, if you
-//! are using a workspace or have a more complicated build setup, this
-//! build script becomes required. Additionally, by requesting that
-//! Cargo re-run the build script whenever `memory.x` is changed,
-//! updating `memory.x` ensures a rebuild of the application with the
-//! new memory settings.
+//! Generates this app's `memory.x` from the workspace's `layout.toml` and
+//! puts it where the linker can always find it at build time.
+//!
+//! The `MEMORY` block comes from the layout: `FLASH` is the app's slot, `RAM`
+//! everything below the reserved words at the top. `sections.x` (the slot
+//! header placement) is appended as is. Cargo re-runs this script whenever
+//! either file changes, so editing the layout relinks the app.
+//!
+//! With the `standalone` feature `FLASH` is the whole of flash instead, which
+//! links the app at the start of flash so it can run without the bootloader.
+
+#[path = "../build-support/layout.rs"]
+mod layout;
 
 use std::env;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
 
 fn main() {
-    // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
-    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
-    File::create(out.join("memory.x"))
-        .unwrap()
-        .write_all(include_bytes!("memory.x"))
-        .unwrap();
-    println!("cargo:rustc-link-search={}", out.display());
-
-    // By default, Cargo will re-run a build script whenever
-    // any file in the project changes. By specifying `memory.x`
-    // here, we ensure the build script is only re-run when
-    // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+    let layout = layout::Layout::load();
+    let flash = if env::var_os("CARGO_FEATURE_STANDALONE").is_some() {
+        layout.flash
+    } else {
+        layout.app2
+    };
+    layout.write_memory_x(flash, &[]);
 }
@@ -0,0 +1,24 @@
+/* Linked after the MEMORY block build.rs generates from layout.toml: FLASH is
+ * the app's slot (all of flash with the `standalone` feature), RAM stops below
+ * the reserved words at its top. Those are the bootloader's NOINIT_RAM (boot
+ * magic and reset cause), the heartbeat and below them the panic ring
+ * (app_common::PanicLog); keeping them out of RAM keeps the stack from
+ * clobbering them. */
+
+/* Image header at a fixed offset just past the vector table (0x198 bytes on
+ * the F411), where the bootloader looks for it. The header gets 0x100 bytes,
+ * so .text is moved to start after that reserved space. */
+SECTIONS
+{
+  .slot_header ORIGIN(FLASH) + 0x200 :
+  {
+    KEEP(*(.slot_header));
+  } > FLASH
+} INSERT AFTER .vector_table;
+
+_stext = ORIGIN(FLASH) + 0x200 + 0x100;
+
+/* The stack grows down from _stack_start; a frame overrunning its top goes
+ * up into the guard gap from layout.toml, never into the reserved words */
+ASSERT(_stack_start + _stack_guard_size <= _reserved_ram_start,
+       "the stack would start on the reserved words, check layout.toml");
@@ -3,70 +3,143 @@
 #![no_main]
 #![no_std]
 
+use app_common::{log, request_boot_after_drain};
+use boot_protocol::{BootRequest, BootTarget, MagicStore};
+#[cfg(not(feature = "panic-log"))]
 use panic_halt as _;
+use stm32f4xx_hal::{pac::USART2, serial::Serial};
 
-/// Jumps to another application via bootloader
+// The IWDG keeps running in Stop, it would reset the sleeping app
+#[cfg(all(feature = "low-power", feature = "watchdog"))]
+compile_error!("features `low-power` and `watchdog` are mutually exclusive");
+
+// Image header read by the bootloader, see boot_protocol::SlotHeader
+boot_protocol::slot_header!();
+
+/// Where the boot magic goes: the shared noinit word (matches bootloader
+/// noinit section)...
+#[cfg(not(feature = "rtc-backup"))]
+fn magic_store() -> impl MagicStore {
+    boot_protocol::NoinitStore::shared()
+}
+
+/// ...or RTC_BKP0R, unlocked during init
+#[cfg(feature = "rtc-backup")]
+fn magic_store() -> impl MagicStore {
+    unsafe { boot_protocol::RtcBackupStore::new() }
+}
+
+/// Switches to `target` via the bootloader
 ///
-/// # Safety
-/// Triggers a system reset after writing magic value to RAM
-pub unsafe fn jump_to_other(_addr: u32) -> ! {
-    use core::ptr::write_volatile;
+/// Lets `uart` finish sending whatever was logged before resetting. While
+/// switching is locked (the `lock` command) it only logs that it ignored the
+/// request.
+#[cfg(not(feature = "no-switch"))]
+pub fn switch_to(uart: &mut Serial<USART2>, target: BootTarget) {
+    if app_common::switching_locked() {
+        log!(uart, "APP2: Switching locked, ignoring");
+        return;
+    }
+    log!(uart, "APP2: Switching to {:?}...", target);
+    // Straight into the other app where possible, interrupt handlers (the
+    // UART command) still go through the reset
+    #[cfg(feature = "debug-jump")]
+    if app_common::in_thread_mode() {
+        app_common::drain(uart);
+        unsafe { app_common::debug_jump(target) }
+    }
+    request_boot_after_drain(&mut magic_store(), target, uart);
+}
 
-    // Magic RAM location and value for App1 (matches bootloader noinit section)
-    const MAGIC_ADDR: *mut u32 = 0x2001_FFF8 as *mut u32;
-    const MAGIC_APP1: u32 = 0xDEAD_BEEF;
+/// Pinned build: the bootloader would ignore the request anyway
+#[cfg(feature = "no-switch")]
+pub fn switch_to(uart: &mut Serial<USART2>, _target: BootTarget) {
+    log!(uart, "APP2: Switching is disabled in this build");
+}
 
-    // Write magic value to RAM
-    write_volatile(MAGIC_ADDR, MAGIC_APP1);
+/// Switches to the next slot holding an app
+///
+/// With no other app installed there is nothing to switch to: logs that and
+/// returns.
+pub fn switch_to_next(uart: &mut Serial<USART2>) {
+    match app_common::next_slot(app_common::current_slot()) {
+        Some(target) => switch_to(uart, target),
+        None => log!(uart, "APP2: No other app installed, staying put"),
+    }
+}
 
-    // Memory barrier
-    cortex_m::asm::dsb();
+/// Resets and comes back to this app, once `uart` has drained
+pub fn restart(uart: &mut Serial<USART2>) -> ! {
+    app_common::restart_self(&mut magic_store(), uart)
+}
 
-    // Trigger system reset using cortex-m API
-    cortex_m::peripheral::SCB::sys_reset();
+/// Resets into the bootloader's update mode, once `uart` has drained
+pub fn reboot_to_update(uart: &mut Serial<USART2>) -> ! {
+    request_boot_after_drain(&mut magic_store(), BootRequest::UpdateMode, uart);
 }
 
 #[rtic::app(device = stm32f4xx_hal::pac, peripherals = true)]
 mod app {
-    use core::fmt::Write;
     use stm32f4xx_hal::{
         gpio::{self, Input, Output, PushPull},
-        pac::{TIM1, USART2},
+        pac::USART2,
         prelude::*,
-        rcc::Config,
-        serial::{config::Config as SerialConfig, Serial},
-        timer,
+        serial::{config::Config as SerialConfig, Event, Serial},
     };
 
-    use crate::jump_to_other;
+    use crate::{reboot_to_update, restart, switch_to_next};
+    use app_common::{log, Action, AppDelay, Blinker, Command, CommandParser};
+    use cortex_m::peripheral::SCB;
 
-    const APP1_ADDR: u32 = 0x08004000; // App1 new address after bootloader
+    // How often idle steps the blinker (and so polls the button)
+    const TICK_MS: u32 = 10;
+
+    // Idle feeds the watchdog once per step
+    const _: () = assert!(TICK_MS <= app_common::MAX_FEED_INTERVAL_MS);
 
     #[shared]
     struct Shared {
         delayval: u32,
+        uart: Serial<USART2>,
     }
 
     #[local]
     struct Local {
-        button: gpio::PA0<Input>,
-        led: gpio::PC13<Output<PushPull>>,
-        delay: timer::DelayMs<TIM1>,
-        last_button_state: bool,
-        uart: Serial<USART2>,
+        scb: SCB,
+        blinker: Blinker<gpio::PA0<Input>, gpio::PC13<Output<PushPull>>>,
+        delay: AppDelay,
+        parser: CommandParser,
     }
 
     #[init]
-    fn init(ctx: init::Context) -> (Shared, Local) {
-        let dp = ctx.device;
-        let rcc = dp.RCC.constrain();
-        let mut rcc = rcc.freeze(Config::hse(25.MHz()));
-        let delay = dp.TIM1.delay_ms(&mut rcc);
+    fn init(mut ctx: init::Context) -> (Shared, Local) {
+        // Before anything deep runs; interrupts are still off during init
+        #[cfg(feature = "stack-paint")]
+        unsafe {
+            app_common::paint_stack();
+        }
+
+        let mut dp = ctx.device;
+        // Same priorities as app1, see app_common::configure_nvic
+        app_common::configure_nvic(&mut ctx.core.NVIC);
+        // Cycle counter for delay_us and the panic log's uptime stamps
+        app_common::enable_cycle_counter(&mut ctx.core.DCB, &mut ctx.core.DWT);
+        let mut rcc = app_common::init_clocks(dp.RCC);
+        // Unlock the backup domain so the boot magic can be stored there
+        #[cfg(feature = "rtc-backup")]
+        unsafe {
+            boot_protocol::enable_backup_access();
+        }
+        let delay = app_common::init_delay(dp.TIM2, &mut rcc);
         let gpioc = dp.GPIOC.split(&mut rcc);
         let led = gpioc.pc13.into_push_pull_output();
         let gpioa: gpio::gpioa::Parts = dp.GPIOA.split(&mut rcc);
-        let button = gpioa.pa0.into_pull_up_input();
-        let last_button_state = button.is_high();
+        // Same pull and press polarity as app1; EXTI0 isn't bound here, so the
+        // line never interrupts and the button is polled in idle instead
+        let mut syscfg = dp.SYSCFG.constrain(&mut rcc);
+        let button = app_common::configure_button(gpioa.pa0, &mut syscfg, &mut dp.EXTI);
+        let delayval = 50_u32;
+        let blinker = Blinker::new(button, led, delayval);
 
         // Configure UART2 for logging (PA2=TX, PA3=RX)
         let tx_pin = gpioa.pa2.into_alternate();
@@ -74,52 +147,162 @@ mod app {
         let mut uart = Serial::new(
             dp.USART2,
             (tx_pin, rx_pin),
-            SerialConfig::default().baudrate(115200.bps()),
+            SerialConfig::default().baudrate(app_common::CONSOLE_BAUD.bps()),
             &mut rcc,
         )
         .unwrap();
-        writeln!(uart, "\r\n=== APP2 STARTING ===").ok();
-        writeln!(uart, "APP2: Init complete - fast blinker mode").ok();
-        writeln!(uart, "APP2: Press button to switch to APP1").ok();
+        // Receive framed commands (see app_core::command) on the same UART
+        uart.listen(Event::RxNotEmpty);
+        log!(uart, "\r\n=== APP2 STARTING ===");
+        log!(uart, "APP2: clocked from {}", app_common::clock_source());
+        log!(uart, "APP2: Init complete - fast blinker mode");
+        log!(uart, "APP2: Press button to switch to APP1");
         (
-            Shared { delayval: 50_u32 },
+            Shared { delayval, uart },
             Local {
-                button,
-                led,
+                scb: ctx.core.SCB,
+                blinker,
                 delay,
-                last_button_state,
-                uart,
+                parser: CommandParser::new(),
             },
         )
     }
 
-    #[idle(local = [led, delay, button, last_button_state, uart], shared = [delayval])]
+    #[idle(local = [scb, blinker, delay], shared = [delayval, uart])]
     fn idle(mut ctx: idle::Context) -> ! {
-        let led = ctx.local.led;
+        let blinker = ctx.local.blinker;
         let delay = ctx.local.delay;
-        let button = ctx.local.button;
-        let last_button_state = ctx.local.last_button_state;
-        let uart = ctx.local.uart;
+        let mut now_ms: u32 = 0;
+
+        // Battery variant: sleep in Stop instead of blinking, the button wakes
+        // the chip up and switches apps. UART commands can't wake it.
+        #[cfg(feature = "low-power")]
+        {
+            // Made it this far, tell the bootloader before Stop, which lasts
+            // until the button. When the switch after waking doesn't happen
+            // (switching locked, or no other app installed) this falls
+            // through into the blink loop below, which ticks and feeds from
+            // then on like without the feature
+            app_common::heartbeat_tick();
+            app_common::feed_watchdog();
+            ctx.shared.uart.lock(|uart| {
+                log!(uart, "APP2: Entering Stop mode until button press");
+                // Stop freezes the UART mid-byte otherwise
+                app_common::drain(uart);
+            });
+            app_common::enter_stop_until_button(blinker.button_mut(), ctx.local.scb);
+            ctx.shared.uart.lock(|uart| {
+                log!(uart, "APP2: Woken by button!");
+                // Boot whichever other app is installed, returns if it can't
+                switch_to_next(uart);
+            });
+            app_common::heartbeat_tick();
+            app_common::feed_watchdog();
+        }
 
         loop {
-            let current_button_state = button.is_high();
-
-            // Detect rising edge (button press) - JUMP TO APP1
-            if current_button_state && !*last_button_state {
-                writeln!(uart, "APP2: Button pressed! Switching to APP1...").ok();
-                // Jump to app1
-                unsafe {
-                    jump_to_other(APP1_ADDR);
-                }
+            blinker.set_half_period(ctx.shared.delayval.lock(|del| *del));
+
+            // Press edge - switch to the other app
+            if let Some(Action::SwitchApp) = blinker.step(now_ms) {
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP2: Button pressed!");
+                    // Boot whichever other app is installed
+                    switch_to_next(uart);
+                });
             }
 
-            *last_button_state = current_button_state;
+            delay.delay_ms(TICK_MS);
+            now_ms = now_ms.wrapping_add(TICK_MS);
+            // Tells the bootloader this image made it into its loop
+            app_common::heartbeat_tick();
+            app_common::feed_watchdog();
+        }
+    }
 
-            // Blink LED
-            led.set_high();
-            delay.delay_ms(ctx.shared.delayval.lock(|del| *del));
-            led.set_low();
-            delay.delay_ms(ctx.shared.delayval.lock(|del| *del));
+    // priority has to match app_common::USART2_PRIORITY
+    #[task(binds = USART2, priority = 1, local = [parser], shared = [uart, delayval])]
+    fn uart_rx_handler(mut ctx: uart_rx_handler::Context) {
+        let byte = ctx.shared.uart.lock(|uart| uart.read().ok());
+        let Some(command) = byte.and_then(|byte| ctx.local.parser.feed(byte)) else {
+            return;
+        };
+
+        match command {
+            Command::SetDelay(ms) => {
+                ctx.shared.delayval.lock(|del| *del = ms);
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP2: Blink delay set to {} ms", ms);
+                });
+            }
+            Command::SwitchApp => {
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP2: Switch requested!");
+                    // Boot whichever other app is installed
+                    switch_to_next(uart);
+                });
+            }
+            Command::Status => {
+                let delay = ctx.shared.delayval.lock(|del| *del);
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP2: running, blink delay {} ms", delay);
+                });
+            }
+            Command::ResetCause => {
+                let cause = app_common::last_reset_cause();
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP2: last reset: {}", cause);
+                });
+            }
+            Command::UpdateMode => {
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP2: Rebooting into update mode...");
+                    reboot_to_update(uart);
+                });
+            }
+            Command::Restart => {
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP2: Restarting...");
+                    restart(uart);
+                });
+            }
+            Command::Lock => {
+                app_common::lock_switching();
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP2: Switching locked");
+                });
+            }
+            Command::Unlock => {
+                app_common::unlock_switching();
+                ctx.shared.uart.lock(|uart| {
+                    log!(uart, "APP2: Switching unlocked");
+                });
+            }
+            Command::StackUsage => {
+                ctx.shared.uart.lock(|uart| {
+                    #[cfg(feature = "stack-paint")]
+                    log!(
+                        uart,
+                        "APP2: stack high water {} of {} bytes",
+                        app_common::stack_high_water(),
+                        app_common::stack_size()
+                    );
+                    #[cfg(not(feature = "stack-paint"))]
+                    log!(uart, "APP2: stack not painted, build with stack-paint");
+                });
+            }
+            Command::Panics => {
+                ctx.shared.uart.lock(|uart| {
+                    let mut count = 0;
+                    for record in app_common::PanicLog::new().iter() {
+                        log!(uart, "APP2: panic {}", record);
+                        count += 1;
+                    }
+                    if count == 0 {
+                        log!(uart, "APP2: no panics recorded");
+                    }
+                });
+            }
         }
     }
 }
@@ -0,0 +1,131 @@
+//! Switching images without cutting off the last log line
+//!
+//! `writeln!` returns once the last byte is in the UART's data register, not
+//! when it has left the pin, so resetting straight away truncates the message.
+//! Draining first waits for the transmission-complete flag.
+//!
+//! [`debug_jump`] is the exception: a switch with no reset at all, for
+//! debugging.
+
+use boot_protocol::{request_boot, BootRequest, BootTarget, MagicStore};
+use embedded_hal_nb::serial::Write;
+
+/// TC polls before giving up on the drain
+///
+/// Only two bytes (data register + shift register) can be in flight, about
+/// 175us at 115200 baud; this allows milliseconds even at 100 MHz.
+const DRAIN_POLLS: u32 = 100_000;
+
+/// Waits for `uart` to finish transmitting, for at most [`DRAIN_POLLS`] polls
+///
+/// Gives up early on a UART error, which waiting won't fix.
+pub fn drain<W: Write<u8>>(uart: &mut W) {
+    for _ in 0..DRAIN_POLLS {
+        match uart.flush() {
+            Err(nb::Error::WouldBlock) => continue,
+            Ok(()) | Err(nb::Error::Other(_)) => break,
+        }
+    }
+}
+
+/// Like [`request_boot`], but lets `uart` finish transmitting first
+///
+/// The wait is capped (see [`drain`]) so a wedged UART can't hold off the
+/// reset: after the timeout, or on a UART error, the reset goes ahead anyway.
+pub fn request_boot_after_drain<S: MagicStore, W: Write<u8>>(
+    store: &mut S,
+    target: impl Into<BootRequest>,
+    uart: &mut W,
+) -> ! {
+    drain(uart);
+    request_boot(store, target)
+}
+
+/// Restarts the running app through a reset, with every peripheral back in
+/// its reset state
+///
+/// Asks the bootloader for this app's own slot, so unlike a boot without a
+/// request it comes back here rather than to the default. The slot (its
+/// role, see [`current_slot`](crate::current_slot)) is taken before the magic
+/// is written. Run standalone there is no slot to ask for and it's a plain
+/// reset, which starts the same image anyway.
+pub fn restart_self<S: MagicStore, W: Write<u8>>(store: &mut S, uart: &mut W) -> ! {
+    match crate::current_slot() {
+        Some(current) => request_boot_after_drain(store, current, uart),
+        None => {
+            drain(uart);
+            cortex_m::peripheral::SCB::sys_reset()
+        }
+    }
+}
+
+/// Whether the core runs in thread mode, outside any exception handler
+pub fn in_thread_mode() -> bool {
+    cortex_m::peripheral::SCB::vect_active() == cortex_m::peripheral::scb::VectActive::ThreadMode
+}
+
+/// Starts `target` (a role, like a boot request) straight away, without a
+/// reset or the bootloader
+///
+/// For debugging the hand-off itself: the bootloader's validation, update
+/// mode and storm guard are all skipped. The peripherals stay as this app
+/// configured them, only interrupts are quiesced.
+///
+/// The steps are the bootloader's hand-off, see `bootloader::jump`, plus
+/// BASEPRI back to 0 (under an RTIC lock it holds the ceiling). Coming from a
+/// running app the teardown does real work here: this app's SysTick and NVIC
+/// lines are live and would fire into the other app's handlers, and its
+/// stack sits where the new app's startup zeroes `.bss`, so the new MSP is
+/// what lets that startup run at all.
+///
+/// It can't return: the caller's stack and statics are gone the moment the
+/// new app starts up.
+///
+/// # Safety
+/// `target` must hold a valid image (see `BootTarget::has_valid_vectors`) and
+/// the caller must be in thread mode ([`in_thread_mode`]). From a handler the
+/// app would start with the exception still active, and everything at or
+/// below its priority would never run.
+pub unsafe fn debug_jump(target: BootTarget) -> ! {
+    use cortex_m::peripheral::{NVIC, SCB, SYST};
+    use cortex_m::register::control::{self, Fpca, Npriv, Spsel};
+
+    // ICSR: PENDSVCLR, PENDSTCLR
+    const ICSR_CLEAR_PENDING: u32 = (1 << 27) | (1 << 25);
+
+    cortex_m::interrupt::disable();
+    // Called under an RTIC lock, BASEPRI holds the lock's ceiling; out of
+    // reset it is 0, and the new app expects that
+    cortex_m::register::basepri::write(0);
+
+    // Nothing this app set up may fire once the other one unmasks
+    (*SYST::PTR).csr.write(0);
+    let nvic = &*NVIC::PTR;
+    for (icer, icpr) in nvic.icer.iter().zip(nvic.icpr.iter()) {
+        icer.write(u32::MAX);
+        icpr.write(u32::MAX);
+    }
+    let scb = &*SCB::PTR;
+    scb.icsr.write(ICSR_CLEAR_PENDING);
+
+    // Privileged thread mode on MSP, no lazy FP state carried over
+    let mut ctrl = control::read();
+    ctrl.set_spsel(Spsel::Msp);
+    ctrl.set_npriv(Npriv::Privileged);
+    ctrl.set_fpca(Fpca::NotActive);
+    control::write(ctrl);
+    cortex_m::asm::isb();
+
+    // `target` is a role, like a boot request
+    let addr = boot_protocol::SlotRoles::load().slot(target).addr();
+    scb.vtor.write(addr);
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+    // PRIMASK as out of reset; every line is disabled, so nothing fires
+    cortex_m::interrupt::enable();
+
+    let vector_table = addr as *const u32;
+    let msp = core::ptr::read_volatile(vector_table);
+    let reset = core::ptr::read_volatile(vector_table.add(1));
+    cortex_m::asm::bootstrap(msp as *const u32, reset as *const u32)
+}
@@ -0,0 +1,92 @@
+//! User button polarity and EXTI setup
+//!
+//! By default a press drives the pin high: it is detected on the rising edge
+//! / high level, and the internal pull-down holds the pin low in between.
+//! Boards that wire the button to ground, where a press pulls the pin low,
+//! build with the `button-active-low` feature instead, which samples with
+//! the pull-up and detects the falling edge / low level.
+//!
+//! EXTI lines are shared between ports: EXTI0 is pin 0 of whichever port
+//! `SYSCFG_EXTICR1` selects, PA0 after reset. [`route_exti_line`] points the
+//! line at the button's own port, so a board with the button on e.g. PB0
+//! gets its edges rather than PA0's.
+
+use stm32f4xx_hal::{
+    gpio::{Edge, ExtiPin, Input, Pin},
+    pac::{self, EXTI},
+    syscfg::SysCfg,
+};
+
+/// Edge that marks a button press
+#[cfg(not(feature = "button-active-low"))]
+pub const BUTTON_EDGE: Edge = Edge::Rising;
+#[cfg(feature = "button-active-low")]
+pub const BUTTON_EDGE: Edge = Edge::Falling;
+
+/// Configures the button pin's pull and its EXTI line for a press edge
+///
+/// The pull holds the pin at its released level: down for an active-high
+/// button, up with `button-active-low`.
+///
+/// The interrupt is enabled at the EXTI level only; it fires once the app
+/// binds a task to the line. Apps that poll can use [`is_pressed`] instead.
+pub fn configure_button<const P: char, const N: u8>(
+    button: Pin<P, N, Input>,
+    syscfg: &mut SysCfg,
+    exti: &mut EXTI,
+) -> Pin<P, N, Input> {
+    #[cfg(not(feature = "button-active-low"))]
+    let mut button = button.into_pull_down_input();
+    #[cfg(feature = "button-active-low")]
+    let mut button = button.into_pull_up_input();
+
+    route_exti_line::<P, N>(syscfg);
+    button.trigger_on_edge(exti, BUTTON_EDGE);
+    button.enable_interrupt(exti);
+    button
+}
+
+/// `SYSCFG_EXTICRx` source code for GPIO port `port`, as far as the F411 has
+/// them (A to E and H)
+pub const fn exti_port_code(port: char) -> u32 {
+    match port {
+        'A' => 0,
+        'B' => 1,
+        'C' => 2,
+        'D' => 3,
+        'E' => 4,
+        'H' => 7,
+        _ => panic!("no such GPIO port on the STM32F411"),
+    }
+}
+
+/// Routes EXTI line `N` to port `P`, returning the code now in its EXTICR
+/// field
+///
+/// Four lines per register, four bits each: `SYSCFG_EXTICR1` holds lines 0-3,
+/// `EXTICR2` 4-7 and so on. The other lines' fields are left alone. Taking
+/// the `SysCfg` makes sure the SYSCFG clock is on.
+pub fn route_exti_line<const P: char, const N: u8>(_syscfg: &mut SysCfg) -> u32 {
+    let code = const { exti_port_code(P) };
+    let shift = 4 * (N as usize % 4);
+
+    // EXTICR1..EXTICR4 are consecutive words from offset 0x08
+    let exticr = unsafe { (pac::SYSCFG::ptr() as *mut u32).add(2 + N as usize / 4) };
+    cortex_m::interrupt::free(|_| unsafe {
+        let value = exticr.read_volatile() & !(0xF << shift);
+        exticr.write_volatile(value | (code << shift));
+    });
+
+    let routed = (unsafe { exticr.read_volatile() } >> shift) & 0xF;
+    debug_assert_eq!(routed, code, "EXTI{} not routed to port {}", N, P);
+    routed
+}
+
+/// Whether the button is currently held, honouring the board's polarity
+pub fn is_pressed<const P: char, const N: u8>(button: &Pin<P, N, Input>) -> bool {
+    if cfg!(feature = "button-active-low") {
+        button.is_low()
+    } else {
+        button.is_high()
+    }
+}
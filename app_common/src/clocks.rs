@@ -0,0 +1,147 @@
+//! Clock tree setup shared by the apps
+//!
+//! The core always ends up at [`SYSCLK_HZ`], whatever crystal the board has:
+//! with the Blackpill's 25 MHz crystal SYSCLK is the HSE itself, an 8 MHz
+//! crystal (`hse-8mhz`) goes through the PLL (8 / 4 * 100 / 8 = 25 MHz). Keeping
+//! the core clock the same means UART baud rates, `CORE_HZ` and every delay
+//! stay right on both boards.
+//!
+//! A crystal that doesn't start (not fitted, broken, wrong load caps) would
+//! hang the HAL's `freeze` forever. [`init_clocks`] gives it
+//! [`HSE_STARTUP_MS`] and otherwise runs the PLL off the internal 16 MHz HSI,
+//! to the same [`SYSCLK_HZ`]: less accurate, but the board stays usable.
+//! Peripherals set up from the returned `Rcc` (the UART's baud divisor
+//! among them) follow whichever source won.
+
+use stm32f4xx_hal::{pac, prelude::*, rcc::Config, rcc::Rcc, serial::Serial};
+
+/// Crystal fitted on the board, 25 MHz unless built with `hse-8mhz`
+pub const HSE_HZ: u32 = if cfg!(feature = "hse-8mhz") {
+    8_000_000
+} else {
+    25_000_000
+};
+
+/// Core clock the apps run at, on any supported crystal
+pub const SYSCLK_HZ: u32 = 25_000_000;
+
+/// How long the crystal gets to start before the HSI takes over
+pub const HSE_STARTUP_MS: u32 = 100;
+
+/// Baud rate of the apps' console
+pub const CONSOLE_BAUD: u32 = 115_200;
+
+// The HSI the chip runs on out of reset, and so while waiting for the HSE
+const HSI_HZ: u32 = 16_000_000;
+
+// RCC_CR bits
+const CR_HSEON: u32 = 1 << 16;
+const CR_HSERDY: u32 = 1 << 17;
+
+// RCC_CFGR system clock status, and RCC_PLLCFGR PLL source
+const CFGR_SWS_SHIFT: u32 = 2;
+const CFGR_SWS_MASK: u32 = 0b11;
+const SWS_HSE: u32 = 0b01;
+const SWS_PLL: u32 = 0b10;
+const PLLCFGR_PLLSRC_HSE: u32 = 1 << 22;
+
+// The F411 takes a 4-26 MHz crystal and runs its core at up to 100 MHz
+const _: () = assert!(HSE_HZ >= 4_000_000 && HSE_HZ <= 26_000_000);
+const _: () = assert!(SYSCLK_HZ <= 100_000_000);
+
+// USART2 is on APB1, which the HAL leaves undivided up to 50 MHz. With 16x
+// oversampling the divisor is PCLK1 / baud, rounded; the receiver tolerates a
+// few percent, keep the rounding error under 1% on either source (both end
+// up at the same SYSCLK)
+const _: () = assert!(SYSCLK_HZ <= 50_000_000);
+const _: () = assert!(baud_error_ppm(SYSCLK_HZ, CONSOLE_BAUD) < 10_000);
+
+/// Where SYSCLK comes from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ClockSource {
+    /// The crystal, directly or through the PLL
+    Hse,
+    /// The internal RC oscillator, the crystal didn't start
+    Hsi,
+}
+
+impl ClockSource {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ClockSource::Hse => "HSE",
+            ClockSource::Hsi => "HSI",
+        }
+    }
+}
+
+impl core::fmt::Display for ClockSource {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Deviation of the baud rate actually produced from `baud`, in ppm
+const fn baud_error_ppm(pclk_hz: u32, baud: u32) -> u32 {
+    let div = (pclk_hz + baud / 2) / baud;
+    let actual = pclk_hz / div;
+    let diff = if actual > baud {
+        actual - baud
+    } else {
+        baud - actual
+    };
+    (diff as u64 * 1_000_000 / baud as u64) as u32
+}
+
+/// Turns the HSE on and waits up to [`HSE_STARTUP_MS`] for it, switching it
+/// off again if it never gets ready
+fn start_hse(rcc: &pac::RCC) -> bool {
+    rcc.cr()
+        .modify(|r, w| unsafe { w.bits(r.bits() | CR_HSEON) });
+    // Polled every 100 us, still on the HSI
+    for _ in 0..HSE_STARTUP_MS * 10 {
+        if rcc.cr().read().bits() & CR_HSERDY != 0 {
+            return true;
+        }
+        cortex_m::asm::delay(HSI_HZ / 10_000);
+    }
+    rcc.cr()
+        .modify(|r, w| unsafe { w.bits(r.bits() & !CR_HSEON) });
+    false
+}
+
+/// Sets SYSCLK to [`SYSCLK_HZ`], from the HSE if it starts and from the HSI
+/// otherwise (see [`clock_source`])
+pub fn init_clocks(rcc: pac::RCC) -> Rcc {
+    let config = if start_hse(&rcc) {
+        Config::hse(HSE_HZ.Hz())
+    } else {
+        Config::hsi()
+    };
+    rcc.constrain().freeze(config.sysclk(SYSCLK_HZ.Hz()))
+}
+
+/// The source SYSCLK currently runs from, as the RCC reports it
+pub fn clock_source() -> ClockSource {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let sws = (rcc.cfgr().read().bits() >> CFGR_SWS_SHIFT) & CFGR_SWS_MASK;
+    let pll_on_hse = rcc.pllcfgr().read().bits() & PLLCFGR_PLLSRC_HSE != 0;
+    match sws {
+        SWS_HSE => ClockSource::Hse,
+        SWS_PLL if pll_on_hse => ClockSource::Hse,
+        _ => ClockSource::Hsi,
+    }
+}
+
+/// Recomputes the console UART's baud divisor from the clocks in `rcc`
+///
+/// For after the clock tree was reconfigured behind the serial driver's
+/// back: the divisor `Serial::new` worked out belongs to the old PCLK1.
+/// Lets `uart` drain first, a byte on the wire would be garbled by the
+/// switch. 16x oversampling, as the HAL configures it.
+pub fn reinit_serial_for_clocks(uart: &mut Serial<pac::USART2>, rcc: &Rcc) {
+    crate::drain(uart);
+    let pclk = rcc.clocks.pclk1().raw();
+    let div = (pclk + CONSOLE_BAUD / 2) / CONSOLE_BAUD;
+    let usart = unsafe { &*pac::USART2::ptr() };
+    usart.brr().write(|w| unsafe { w.bits(div) });
+}
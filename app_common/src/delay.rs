@@ -0,0 +1,67 @@
+//! Timer backing the apps' blocking delay, and short cycle-counted delays
+//!
+//! The delay runs on TIM2, which leaves TIM1 (the only advanced timer, with
+//! complementary outputs) free for PWM. Neither app uses a timer for anything
+//! else, so TIM1, TIM3, TIM4, TIM5 and TIM9-11 are all available to
+//! application code. To move the delay to another timer, change
+//! [`DelayTimer`]; the apps only name [`AppDelay`].
+//!
+//! For sub-millisecond waits [`delay_cycles`] and [`delay_us`] count core
+//! cycles on the DWT instead, no timer needed. The counter has to be turned
+//! on once with [`enable_cycle_counter`].
+
+use cortex_m::peripheral::{DCB, DWT};
+use stm32f4xx_hal::{pac, rcc::Rcc, timer::DelayMs, timer::TimerExt};
+
+/// Core clock the apps run at, as set up by `init_clocks`
+///
+/// `delay_us` converts with this.
+pub const CORE_HZ: u32 = crate::clocks::SYSCLK_HZ;
+
+// DWT_CTRL.CYCCNTENA
+const CTRL_CYCCNTENA: u32 = 1 << 0;
+
+/// The timer the blocking delay is built on
+pub type DelayTimer = pac::TIM2;
+
+/// Millisecond delay handle the apps keep in their `Local` resources
+pub type AppDelay = DelayMs<DelayTimer>;
+
+/// Turns the delay timer into a millisecond delay
+pub fn init_delay(tim: DelayTimer, rcc: &mut Rcc) -> AppDelay {
+    tim.delay_ms(rcc)
+}
+
+/// Starts the DWT cycle counter behind `delay_cycles`/`delay_us`
+///
+/// Sets `DEMCR.TRCENA` too, without it the DWT isn't clocked. A debugger
+/// may have done both already, but a bare reset clears them.
+pub fn enable_cycle_counter(dcb: &mut DCB, dwt: &mut DWT) {
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+}
+
+/// Whether the cycle counter is running
+pub fn cycle_counter_running() -> bool {
+    unsafe { (*DWT::PTR).ctrl.read() & CTRL_CYCCNTENA != 0 }
+}
+
+/// Busy-waits for `n` core cycles
+///
+/// Counted on the DWT, so interrupts taken meanwhile don't stretch the wait.
+/// Works across the counter wrapping, for any `n` below 2^32. Without the
+/// counter (`enable_cycle_counter` not called) falls back to a calibrated
+/// spin loop, which is at least `n` cycles but stretched by interrupts.
+pub fn delay_cycles(n: u32) {
+    if !cycle_counter_running() {
+        cortex_m::asm::delay(n);
+        return;
+    }
+    let start = DWT::cycle_count();
+    while DWT::cycle_count().wrapping_sub(start) < n {}
+}
+
+/// Busy-waits for `us` microseconds, assuming the core runs at `CORE_HZ`
+pub fn delay_us(us: u32) {
+    delay_cycles(us.saturating_mul(CORE_HZ / 1_000_000));
+}
@@ -0,0 +1,57 @@
+//! Helpers shared by the applications
+//!
+//! Anything both apps need to agree on (button polarity, the boot handshake)
+//! lives here so a board variant only has to be described once.
+
+#![no_std]
+
+// defmt's global logger, only linked in when lines go to RTT
+#[cfg(feature = "log-rtt")]
+use defmt_rtt as _;
+
+mod boot;
+mod button;
+mod clocks;
+mod delay;
+mod lock;
+pub mod log;
+mod nvic;
+mod panic_log;
+mod power;
+mod slots;
+mod stack;
+mod watchdog;
+
+pub use app_core::{
+    crc8, Action, AppSwitcher, Blinker, Command, CommandParser, Led, DEBOUNCE_MS, ETX, MAX_PAYLOAD,
+    STX,
+};
+pub use boot::{debug_jump, drain, in_thread_mode, request_boot_after_drain, restart_self};
+pub use boot_protocol::{
+    heartbeat_tick, request_boot, BootRequest, BootTarget, MagicStore, ResetCause,
+};
+pub use button::{configure_button, exti_port_code, is_pressed, route_exti_line, BUTTON_EDGE};
+pub use clocks::{
+    clock_source, init_clocks, reinit_serial_for_clocks, ClockSource, CONSOLE_BAUD, HSE_HZ,
+    HSE_STARTUP_MS, SYSCLK_HZ,
+};
+pub use delay::{
+    cycle_counter_running, delay_cycles, delay_us, enable_cycle_counter, init_delay, AppDelay,
+    DelayTimer, CORE_HZ,
+};
+pub use lock::{lock_switching, switching_locked, unlock_switching};
+pub use log::UartSink;
+pub use nvic::{configure_nvic, EXTI0_PRIORITY, EXTI1_PRIORITY, USART2_PRIORITY};
+pub use panic_log::{PanicLog, PanicRecord, PANIC_LOG_LEN};
+pub use power::enter_stop_until_button;
+pub use slots::{current_slot, next_slot};
+pub use stack::{paint_stack, stack_high_water, stack_size, STACK_PAINT};
+pub use watchdog::{feed_watchdog, MAX_FEED_INTERVAL_MS, WATCHDOG_TIMEOUT_MS};
+
+/// Why the chip last reset, as decoded and stashed by the bootloader
+///
+/// Readable at any time: the stash lives in the noinit word the apps' linker
+/// scripts keep out of RAM, so nothing in the app overwrites it.
+pub fn last_reset_cause() -> ResetCause {
+    boot_protocol::stashed_reset_cause()
+}
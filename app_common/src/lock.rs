@@ -0,0 +1,25 @@
+//! Runtime lock on switching apps, for demos that should stay in one app
+//!
+//! While locked the apps ignore switch requests, from the button and the UART
+//! alike (they log that they did). The UART `lock`/`unlock` commands toggle
+//! it. Unlike a pinned build (`no-switch`, the bootloader's `force-app*`) it
+//! needs no reflash, and it is kept in RAM only: any reset, including the
+//! `restart` command, starts out unlocked.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Whether switch requests are being ignored
+pub fn switching_locked() -> bool {
+    LOCKED.load(Ordering::Relaxed)
+}
+
+/// Ignores switch requests until [`unlock_switching`]
+pub fn lock_switching() {
+    LOCKED.store(true, Ordering::Relaxed);
+}
+
+pub fn unlock_switching() {
+    LOCKED.store(false, Ordering::Relaxed);
+}
@@ -0,0 +1,98 @@
+//! Line logger behind [`log!`](crate::log)
+//!
+//! Every call formats one complete line into a buffer first and then hands it
+//! to the enabled transports: the UART (`log-uart`, the default), defmt over
+//! RTT (`log-rtt`), or both. A line therefore always reaches the UART in one
+//! piece, and since apps log while holding the UART resource a preempting
+//! task can't wedge its own output into the middle of it.
+
+use core::fmt::{self, Write};
+use stm32f4xx_hal::{pac::USART2, serial::Serial};
+
+/// Longest line kept, anything past it is cut off
+pub const MAX_LINE: usize = 128;
+
+/// Where `log!` sends the UART copy of a line
+///
+/// `None` from [`uart`](UartSink::uart) drops the UART copy, so a console that
+/// failed to come up (kept as `Option<Serial<_>>`) doesn't stop the RTT copy.
+pub trait UartSink {
+    fn uart(&mut self) -> Option<&mut dyn Write>;
+}
+
+impl UartSink for Serial<USART2> {
+    fn uart(&mut self) -> Option<&mut dyn Write> {
+        Some(self)
+    }
+}
+
+impl<T: UartSink> UartSink for Option<T> {
+    fn uart(&mut self) -> Option<&mut dyn Write> {
+        self.as_mut().and_then(UartSink::uart)
+    }
+}
+
+/// One line being formatted, truncated at `MAX_LINE` bytes
+struct Line {
+    buf: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            buf: [0; MAX_LINE],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only whole `str`s (or char-boundary prefixes of them) are copied in
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut n = s.len().min(MAX_LINE - self.len);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Formats `args` as one line and sends it to every enabled transport
+#[doc(hidden)]
+pub fn emit(uart: Option<&mut dyn Write>, args: fmt::Arguments) {
+    let mut line = Line::new();
+    line.write_fmt(args).ok();
+
+    // RTT frames are lines already, the console's blank-line padding isn't needed
+    #[cfg(feature = "log-rtt")]
+    defmt::println!("{=str}", line.as_str().trim());
+
+    #[cfg(feature = "log-uart")]
+    if let Some(uart) = uart {
+        uart.write_str(line.as_str()).ok();
+        uart.write_str("\n").ok();
+    }
+    #[cfg(not(feature = "log-uart"))]
+    let _ = uart;
+}
+
+/// Logs a line, like `writeln!` on the console UART but mirrored to RTT
+/// when `log-rtt` is enabled
+///
+/// The first argument is the UART (anything implementing [`UartSink`]),
+/// the rest is a format string and its arguments.
+#[macro_export]
+macro_rules! log {
+    ($uart:expr, $($arg:tt)*) => {{
+        // Method call so both `Serial` and `&mut Serial` work without `&mut`
+        use $crate::log::UartSink as _;
+        $crate::log::emit($uart.uart(), format_args!($($arg)*))
+    }};
+}
@@ -0,0 +1,50 @@
+//! Interrupt priorities shared by the apps
+//!
+//! A reset clears every NVIC priority, a [`debug_jump`](crate::debug_jump)
+//! doesn't: it masks and unpends all lines but leaves the priorities the
+//! previous app programmed. So the lines either app uses get a fixed priority
+//! here, whichever way the app was entered.
+//!
+//! Priorities are RTIC's logical ones (1 lowest, higher preempts). RTIC
+//! programs the lines it binds itself before `init`; a bound line's constant
+//! here has to match its task's `priority`, otherwise the override breaks
+//! RTIC's locks.
+
+use cortex_m::peripheral::NVIC;
+use stm32f4xx_hal::pac::{Interrupt, NVIC_PRIO_BITS};
+
+/// The button line (PA0). Not bound, only unmasked around Stop mode, see
+/// [`enter_stop_until_button`](crate::enter_stop_until_button)
+pub const EXTI0_PRIORITY: u8 = 1;
+
+/// EXTI1, not used by the apps yet: kept at the bottom so a stray line
+/// preempts nothing
+pub const EXTI1_PRIORITY: u8 = 1;
+
+/// The UART command task, `#[task(binds = USART2)]` at RTIC's default priority
+pub const USART2_PRIORITY: u8 = 1;
+
+const _: () = assert!(EXTI0_PRIORITY >= 1 && EXTI0_PRIORITY <= 1 << NVIC_PRIO_BITS);
+const _: () = assert!(EXTI1_PRIORITY >= 1 && EXTI1_PRIORITY <= 1 << NVIC_PRIO_BITS);
+const _: () = assert!(USART2_PRIORITY >= 1 && USART2_PRIORITY <= 1 << NVIC_PRIO_BITS);
+
+/// Register value for a logical priority, the same mapping RTIC uses
+const fn hw_priority(logical: u8) -> u8 {
+    ((1 << NVIC_PRIO_BITS) - logical) << (8 - NVIC_PRIO_BITS)
+}
+
+/// Programs the apps' priorities for EXTI0, EXTI1 and USART2
+///
+/// Call it from `init`, where interrupts are still off. Masking is left
+/// alone: RTIC unmasks the bound lines after `init`.
+pub fn configure_nvic(nvic: &mut NVIC) {
+    let lines = [
+        (Interrupt::EXTI0, EXTI0_PRIORITY),
+        (Interrupt::EXTI1, EXTI1_PRIORITY),
+        (Interrupt::USART2, USART2_PRIORITY),
+    ];
+    for (irq, priority) in lines {
+        // Only changes preemption between lines, which is what's being pinned
+        unsafe { nvic.set_priority(irq, hw_priority(priority)) };
+    }
+}
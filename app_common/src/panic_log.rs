@@ -0,0 +1,240 @@
+//! The last few panics, kept in noinit RAM across resets
+//!
+//! The ring sits at `PANIC_LOG_ADDR`, below the boot magic, and every image's
+//! linker script keeps it out of RAM. Layout:
+//!
+//! ```text
+//! magic | head | record[0] .. record[PANIC_LOG_LEN - 1]
+//! ```
+//!
+//! `head` counts the records ever pushed, the newest one is in
+//! `record[(head - 1) % PANIC_LOG_LEN]`. A push writes the whole record first
+//! and bumps `head` last, so a panic (or reset) in the middle of a push leaves
+//! at most that one slot torn. Each record carries its own sequence number and
+//! checksum, a torn one fails them and is skipped instead of taking the rest of
+//! the log with it.
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+use boot_protocol::{NoinitCell, PANIC_LOG_ADDR, PANIC_LOG_SIZE};
+
+/// Records the ring holds, older ones are overwritten
+pub const PANIC_LOG_LEN: usize = 3;
+
+// Marks the ring as initialised, RAM powers up with anything in it
+const PANIC_LOG_MAGIC: u32 = 0x9A1C_0F55;
+
+const HEAD_ADDR: u32 = PANIC_LOG_ADDR + 4;
+const RECORDS_ADDR: u32 = PANIC_LOG_ADDR + 8;
+
+const FILE_LEN: usize = 16;
+const MESSAGE_LEN: usize = 32;
+
+const _: () =
+    assert!(8 + PANIC_LOG_LEN * core::mem::size_of::<PanicRecord>() <= PANIC_LOG_SIZE as usize);
+
+/// One stored panic
+///
+/// `file` keeps the tail of the source path and `message` the start of the
+/// panic message, both NUL padded and cut to fit.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PanicRecord {
+    /// 1 for the first panic since the log was (re)initialised, set by `push`
+    pub seq: u32,
+    /// Time since reset, see `PanicRecord::from_info`
+    pub uptime_ms: u32,
+    pub line: u32,
+    check: u32,
+    file: [u8; FILE_LEN],
+    message: [u8; MESSAGE_LEN],
+}
+
+impl PanicRecord {
+    /// A record for `info`, taken `uptime_ms` after reset
+    pub fn from_info(info: &PanicInfo, uptime_ms: u32) -> Self {
+        let mut record = Self {
+            seq: 0,
+            uptime_ms,
+            line: 0,
+            check: 0,
+            file: [0; FILE_LEN],
+            message: [0; MESSAGE_LEN],
+        };
+        if let Some(location) = info.location() {
+            record.line = location.line();
+            let path = location.file().as_bytes();
+            let tail = &path[path.len().saturating_sub(FILE_LEN)..];
+            record.file[..tail.len()].copy_from_slice(tail);
+        }
+        // Too long is fine: Truncate keeps what fits
+        write!(Truncate::new(&mut record.message), "{}", info.message()).ok();
+        record
+    }
+
+    /// Source file the panic came from (its last `FILE_LEN` bytes)
+    pub fn file(&self) -> &str {
+        text(&self.file)
+    }
+
+    /// Start of the panic message
+    pub fn message(&self) -> &str {
+        text(&self.message)
+    }
+
+    // FNV-1a over everything but `check` itself
+    fn checksum(&self) -> u32 {
+        let words = [self.seq, self.uptime_ms, self.line];
+        words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .chain(self.file.iter().copied())
+            .chain(self.message.iter().copied())
+            .fold(0x811C_9DC5, |hash, byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+            })
+    }
+}
+
+impl fmt::Display for PanicRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} at {} ms: {}:{}: {}",
+            self.seq,
+            self.uptime_ms,
+            self.file(),
+            self.line,
+            self.message()
+        )
+    }
+}
+
+// Up to the first NUL; a cut in the middle of a UTF-8 sequence drops it
+fn text(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    match core::str::from_utf8(&bytes[..len]) {
+        Ok(text) => text,
+        Err(err) => core::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+/// Writes into a fixed buffer, silently dropping what doesn't fit
+struct Truncate<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Truncate<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+}
+
+impl Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Handle on the panic ring
+///
+/// There is only the one ring; handles are free to make, e.g. from a panic
+/// handler and a command task. Both touch it whole-record and the apps are
+/// single-core, but a push preempting an iteration can make it skip a record.
+pub struct PanicLog {
+    _private: (),
+}
+
+impl PanicLog {
+    pub const fn new() -> Self {
+        Self { _private: () }
+    }
+
+    fn magic() -> &'static NoinitCell<u32> {
+        // PANIC_LOG_ADDR is reserved by every image's linker script
+        unsafe { NoinitCell::at(PANIC_LOG_ADDR) }
+    }
+
+    fn head() -> &'static NoinitCell<u32> {
+        unsafe { NoinitCell::at(HEAD_ADDR) }
+    }
+
+    fn slot(index: usize) -> &'static NoinitCell<PanicRecord> {
+        let offset = (index % PANIC_LOG_LEN) * core::mem::size_of::<PanicRecord>();
+        unsafe { NoinitCell::at(RECORDS_ADDR + offset as u32) }
+    }
+
+    /// Records pushed so far, 0 until the ring has been initialised
+    fn pushed(&self) -> u32 {
+        if Self::magic().read() == PANIC_LOG_MAGIC {
+            Self::head().read()
+        } else {
+            0
+        }
+    }
+
+    /// Forgets every stored record
+    pub fn clear(&mut self) {
+        Self::head().write(0);
+        Self::magic().write(PANIC_LOG_MAGIC);
+    }
+
+    /// Stores `record` as the newest entry, overwriting the oldest one once
+    /// the ring is full
+    pub fn push(&mut self, mut record: PanicRecord) {
+        if Self::magic().read() != PANIC_LOG_MAGIC {
+            self.clear();
+        }
+        let head = Self::head().read();
+        record.seq = head.wrapping_add(1);
+        record.check = record.checksum();
+        Self::slot(head as usize).write(record);
+        // Commit: only now does the record count as stored
+        Self::head().write(head.wrapping_add(1));
+    }
+
+    /// Stored records, oldest first; torn ones are left out
+    pub fn iter(&self) -> impl Iterator<Item = PanicRecord> {
+        let head = self.pushed();
+        let first = head.saturating_sub(PANIC_LOG_LEN as u32);
+        (first..head).filter_map(|index| {
+            let record = Self::slot(index as usize).read();
+            let intact = record.seq == index + 1 && record.check == record.checksum();
+            intact.then_some(record)
+        })
+    }
+}
+
+impl Default for PanicLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Milliseconds since the app's init started the DWT cycle counter
+/// (`enable_cycle_counter`), 0 if it isn't running. Wraps after about 171 s at
+/// `CORE_HZ`.
+#[cfg(feature = "panic-log")]
+fn uptime_ms() -> u32 {
+    if crate::cycle_counter_running() {
+        cortex_m::peripheral::DWT::cycle_count() / (crate::CORE_HZ / 1000)
+    } else {
+        0
+    }
+}
+
+/// Records the panic in the ring, then halts like `panic-halt`
+#[cfg(feature = "panic-log")]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    PanicLog::new().push(PanicRecord::from_info(info, uptime_ms()));
+    loop {
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,91 @@
+//! Stop mode with the button as the wakeup source
+//!
+//! Stop halts every clock except the LSI/LSE, and the core always wakes up
+//! running from the HSI. [`enter_stop_until_button`] saves the clock setup
+//! before going down and brings HSE (and the PLL, if it was on) back before
+//! returning, so the HAL's `Clocks` and everything derived from them (baud
+//! rates, delays) are correct again by the time the caller runs. VTOR and the
+//! rest of the core state are retained through Stop.
+
+use cortex_m::peripheral::{NVIC, SCB};
+use stm32f4xx_hal::{
+    gpio::{ExtiPin, Input, Pin},
+    pac,
+};
+
+// RCC_CR bits
+const CR_HSEON: u32 = 1 << 16;
+const CR_HSERDY: u32 = 1 << 17;
+const CR_PLLON: u32 = 1 << 24;
+const CR_PLLRDY: u32 = 1 << 25;
+
+// RCC_CFGR system clock switch / status
+const CFGR_SW_MASK: u32 = 0b11;
+const CFGR_SWS_SHIFT: u32 = 2;
+
+// PWR_CR: power-down deep sleep (0 = Stop), low-power regulator in Stop
+const PWR_CR_PDDS: u32 = 1 << 1;
+const PWR_CR_LPDS: u32 = 1 << 0;
+
+/// Enters Stop mode and returns once the button's EXTI line fires
+///
+/// `button` must already be an interrupt source on its press edge (see
+/// [`configure_button`](crate::configure_button)). The wait runs with
+/// interrupts masked and the line's pending bits are cleared before they are
+/// unmasked again, so the line needs no bound task. Other enabled interrupts
+/// also end the Stop early; the function then goes back to sleep.
+pub fn enter_stop_until_button<const P: char, const N: u8>(
+    button: &mut Pin<P, N, Input>,
+    scb: &mut SCB,
+) {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let pwr = unsafe { &*pac::PWR::ptr() };
+    let irq = button.interrupt();
+
+    // PWR registers need the PWR clock
+    rcc.apb1enr().modify(|_, w| w.pwren().set_bit());
+
+    cortex_m::interrupt::free(|_| {
+        let cr = rcc.cr().read().bits();
+        let cfgr = rcc.cfgr().read().bits();
+
+        pwr.cr()
+            .modify(|r, w| unsafe { w.bits((r.bits() & !PWR_CR_PDDS) | PWR_CR_LPDS) });
+        scb.set_sleepdeep();
+        button.clear_interrupt_pending_bit();
+        unsafe { NVIC::unmask(irq) };
+
+        // Masked interrupts still end WFI, they just aren't taken
+        while !button.check_interrupt() {
+            cortex_m::asm::dsb();
+            cortex_m::asm::wfi();
+        }
+
+        scb.clear_sleepdeep();
+        restore_clocks(rcc, cr, cfgr);
+
+        NVIC::mask(irq);
+        button.clear_interrupt_pending_bit();
+        NVIC::unpend(irq);
+    });
+}
+
+/// Re-enables the oscillators that were on before Stop and switches SYSCLK
+/// back to its old source
+fn restore_clocks(rcc: &pac::rcc::RegisterBlock, cr: u32, cfgr: u32) {
+    if cr & CR_HSEON != 0 {
+        rcc.cr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | CR_HSEON) });
+        while rcc.cr().read().bits() & CR_HSERDY == 0 {}
+    }
+    if cr & CR_PLLON != 0 {
+        rcc.cr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | CR_PLLON) });
+        while rcc.cr().read().bits() & CR_PLLRDY == 0 {}
+    }
+
+    let sw = cfgr & CFGR_SW_MASK;
+    rcc.cfgr()
+        .modify(|r, w| unsafe { w.bits((r.bits() & !CFGR_SW_MASK) | sw) });
+    while (rcc.cfgr().read().bits() >> CFGR_SWS_SHIFT) & CFGR_SW_MASK != sw {}
+}
@@ -0,0 +1,36 @@
+//! Which slot is running, and which one a switch should go to
+//!
+//! Switching means "boot the next slot that holds an app", worked out at
+//! runtime, so an image never has to hardcode its sibling.
+//!
+//! Both speak in roles, like boot requests do: with the slot roles swapped
+//! (`boot_protocol::SlotRoles`) an image in the App2 slot is `App1`.
+
+use boot_protocol::{BootTarget, SlotRoles};
+use cortex_m::peripheral::SCB;
+
+/// The role of the slot this image runs from, `None` when it isn't in one
+/// (standalone)
+///
+/// Read from VTOR, which the bootloader points at the app's vector table.
+pub fn current_slot() -> Option<BootTarget> {
+    let vtor = unsafe { (*SCB::PTR).vtor.read() };
+    BootTarget::containing(vtor).map(|slot| SlotRoles::load().role(slot))
+}
+
+/// The next role after `current` (wrapping) whose slot has a valid vector
+/// table
+///
+/// `None` if no other slot holds an app, in which case switching isn't
+/// possible and the caller should stay put.
+pub fn next_slot(current: Option<BootTarget>) -> Option<BootTarget> {
+    let roles = SlotRoles::load();
+    let all = BootTarget::ALL;
+    let start = current
+        .and_then(|current| all.iter().position(|&target| target == current))
+        .map_or(0, |index| index + 1);
+
+    (0..all.len())
+        .map(|step| all[(start + step) % all.len()])
+        .find(|&target| Some(target) != current && roles.slot(target).has_valid_vectors())
+}
@@ -0,0 +1,63 @@
+//! Stack painting and high-water mark
+//!
+//! `paint_stack` fills the not yet used part of the stack with [`STACK_PAINT`],
+//! `stack_high_water` later finds the deepest word that no longer holds it.
+//! The bounds come from cortex-m-rt's link.x: the stack grows down from
+//! `_stack_start` towards `__sheap`, the end of the statics. RTIC tasks run on
+//! the same (main) stack, so the mark covers interrupt nesting too.
+
+use core::ptr::{addr_of, read_volatile, write_volatile};
+
+/// Pattern left in stack words that were never used
+pub const STACK_PAINT: u32 = 0x5AC3_5AC3;
+
+// Kept unpainted below the stack pointer, covers paint_stack's own frame
+const PAINT_MARGIN: u32 = 64;
+
+extern "C" {
+    static _stack_start: u32;
+    static __sheap: u32;
+}
+
+fn stack_top() -> u32 {
+    unsafe { addr_of!(_stack_start) as u32 }
+}
+
+fn stack_bottom() -> u32 {
+    unsafe { addr_of!(__sheap) as u32 }
+}
+
+/// Bytes available to the stack
+pub fn stack_size() -> u32 {
+    stack_top() - stack_bottom()
+}
+
+/// Paints the stack below the current frame
+///
+/// Call first thing in `init`, before the stack has been anywhere deep: what
+/// is above the stack pointer at that point counts as used. The words right
+/// below it are skipped so the frame doing the painting is never overwritten.
+///
+/// # Safety
+/// Nothing may live below the current stack pointer, i.e. no other context
+/// (interrupt handler) may be running on this stack.
+pub unsafe fn paint_stack() {
+    let limit = cortex_m::register::msp::read() - PAINT_MARGIN;
+    let mut addr = stack_bottom();
+    while addr < limit {
+        write_volatile(addr as *mut u32, STACK_PAINT);
+        addr += 4;
+    }
+}
+
+/// Deepest stack use so far in bytes, counted from the top of the stack
+///
+/// Only meaningful after `paint_stack`; an unpainted stack reports its full size.
+pub fn stack_high_water() -> u32 {
+    let top = stack_top();
+    let mut addr = stack_bottom();
+    while addr < top && unsafe { read_volatile(addr as *const u32) } == STACK_PAINT {
+        addr += 4;
+    }
+    top - addr
+}
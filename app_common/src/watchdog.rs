@@ -0,0 +1,46 @@
+//! Feeding the independent watchdog (`watchdog` feature)
+//!
+//! This repo's bootloader leaves the IWDG alone. Once started, though, by a
+//! bootloader that does or by the `WDG_SW` option byte (hardware watchdog),
+//! it can't be stopped again, so every loop that can run for a while calls
+//! [`feed_watchdog`]. Without the feature the call compiles to nothing, so
+//! the apps call it unconditionally.
+//!
+//! ## How long a loop may go without feeding
+//!
+//! The IWDG counts the LSI, nominally 32 kHz, but on the F411 anywhere from
+//! 17 to 47 kHz. A timeout programmed as [`WATCHDOG_TIMEOUT_MS`] at 32 kHz can
+//! therefore expire after as little as 32/47 of it, about 0.68. Gaps between
+//! feeds are kept within [`MAX_FEED_INTERVAL_MS`], half the timeout, which
+//! covers that with some margin. Any blocking delay between two feeds has to
+//! be chunked to that: with the 1 s timeout a single `delay_ms` may be at
+//! most 500 ms.
+//!
+//! Both apps feed from the same place they tick the heartbeat. App1 does it
+//! after every `TICK_MS` step of its wait, so even the long pause (a
+//! `delayval` of up to seconds, waited in `PAUSE_CHUNK_MS` chunks of
+//! `TICK_MS` steps) feeds every 10 ms. App2 feeds every `TICK_MS` in idle.
+//!
+//! The IWDG keeps counting in Stop mode, so App2's `low-power` variant,
+//! asleep until the button is pressed, can't be built with it.
+
+/// Watchdog timeout the apps are written for, at the nominal LSI. Whatever
+/// starts the IWDG must program at least this.
+pub const WATCHDOG_TIMEOUT_MS: u32 = 1000;
+
+/// Longest gap between two feeds, see the module docs
+pub const MAX_FEED_INTERVAL_MS: u32 = WATCHDOG_TIMEOUT_MS / 2;
+
+// IWDG_KR value reloading the counter
+#[cfg(feature = "watchdog")]
+const KR_RELOAD: u32 = 0xAAAA;
+
+/// Reloads the IWDG counter, a no-op without the `watchdog` feature
+#[inline]
+pub fn feed_watchdog() {
+    #[cfg(feature = "watchdog")]
+    unsafe {
+        let iwdg = &*stm32f4xx_hal::pac::IWDG::ptr();
+        iwdg.kr().write(|w| w.bits(KR_RELOAD));
+    }
+}
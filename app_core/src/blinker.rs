@@ -0,0 +1,238 @@
+//! App2's fast blinker as a time-driven state machine
+//!
+//! [`Blinker::step`] is called periodically with the current time and does
+//! one slice of work: act on a new button press, or toggle the LED once half
+//! a blink period has elapsed. It never blocks, so whoever calls it decides
+//! how time advances. Presses are debounced by an [`AppSwitcher`].
+
+use crate::io::Led;
+use crate::switcher::AppSwitcher;
+use embedded_hal::digital::InputPin;
+
+/// What the caller has to do after a [`Blinker::step`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// The button was just pressed: switch to the other app
+    SwitchApp,
+}
+
+pub struct Blinker<B, L> {
+    switcher: AppSwitcher<B>,
+    led: L,
+    /// Time the LED spends in each state
+    half_period_ms: u32,
+    led_on: bool,
+    last_toggle_ms: u32,
+}
+
+impl<B: InputPin, L: Led> Blinker<B, L> {
+    /// Starts with the LED off and the button's current state as the baseline,
+    /// so a button held during startup doesn't count as a press
+    pub fn new(button: B, mut led: L, half_period_ms: u32) -> Self {
+        led.off();
+        Self {
+            switcher: AppSwitcher::new(button),
+            led,
+            half_period_ms,
+            led_on: false,
+            last_toggle_ms: 0,
+        }
+    }
+
+    /// The button, e.g. to wait on its EXTI line
+    pub fn button_mut(&mut self) -> &mut B {
+        self.switcher.button_mut()
+    }
+
+    /// Changes the blink speed, takes effect at the next toggle
+    pub fn set_half_period(&mut self, half_period_ms: u32) {
+        self.half_period_ms = half_period_ms;
+    }
+
+    /// Advances the state machine to `now_ms` (a free-running, wrapping clock)
+    pub fn step(&mut self, now_ms: u32) -> Option<Action> {
+        if self.switcher.poll_press(now_ms) {
+            return Some(Action::SwitchApp);
+        }
+
+        if now_ms.wrapping_sub(self.last_toggle_ms) >= self.half_period_ms {
+            self.led_on = !self.led_on;
+            if self.led_on {
+                self.led.on();
+            } else {
+                self.led.off();
+            }
+            self.last_toggle_ms = now_ms;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEBOUNCE_MS;
+    use core::convert::Infallible;
+    use embedded_hal::digital::{ErrorType, OutputPin};
+
+    const HALF: u32 = 100;
+
+    struct MockButton {
+        held: bool,
+    }
+
+    impl ErrorType for MockButton {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockButton {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(self.held != cfg!(feature = "button-active-low"))
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    /// An LED pin that remembers its level and counts the writes to it
+    #[derive(Default)]
+    struct MockLed {
+        high: bool,
+        writes: u32,
+    }
+
+    impl ErrorType for MockLed {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockLed {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.high = false;
+            self.writes += 1;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.high = true;
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    fn blinker(held: bool) -> Blinker<MockButton, MockLed> {
+        let led = MockLed {
+            high: true,
+            writes: 0,
+        };
+        Blinker::new(MockButton { held }, led, HALF)
+    }
+
+    /// Steps every millisecond in `from..to`, returning the times the LED
+    /// changed
+    fn toggles(blinker: &mut Blinker<MockButton, MockLed>, from: u32, to: u32) -> Vec<u32> {
+        (from..to)
+            .filter(|&now| {
+                let before = blinker.led.writes;
+                assert_eq!(blinker.step(now), None);
+                blinker.led.writes != before
+            })
+            .collect()
+    }
+
+    #[test]
+    fn starts_off() {
+        let blinker = blinker(false);
+        assert!(!blinker.led.high);
+    }
+
+    #[test]
+    fn toggles_every_half_period() {
+        let mut blinker = blinker(false);
+        assert_eq!(
+            toggles(&mut blinker, 0, 4 * HALF + 1),
+            [HALF, 2 * HALF, 3 * HALF, 4 * HALF]
+        );
+        assert!(!blinker.led.high);
+    }
+
+    #[test]
+    fn half_period_edges() {
+        let mut blinker = blinker(false);
+        assert_eq!(blinker.step(HALF - 1), None);
+        assert!(!blinker.led.high);
+        blinker.step(HALF);
+        assert!(blinker.led.high);
+        blinker.step(2 * HALF - 1);
+        assert!(blinker.led.high);
+        // A late step toggles late, and the next half period counts from it
+        blinker.step(2 * HALF + 30);
+        assert!(!blinker.led.high);
+        blinker.step(3 * HALF + 29);
+        assert!(!blinker.led.high);
+        blinker.step(3 * HALF + 30);
+        assert!(blinker.led.high);
+    }
+
+    #[test]
+    fn toggles_across_clock_wrap() {
+        let mut blinker = blinker(false);
+        let start = u32::MAX - HALF / 2;
+        blinker.step(start);
+        assert!(blinker.led.high);
+        blinker.step(start.wrapping_add(HALF - 1));
+        assert!(blinker.led.high);
+        blinker.step(start.wrapping_add(HALF));
+        assert!(!blinker.led.high);
+    }
+
+    #[test]
+    fn new_period_applies_from_next_toggle() {
+        let mut blinker = blinker(false);
+        blinker.step(HALF);
+        blinker.set_half_period(10);
+        assert_eq!(
+            toggles(&mut blinker, HALF + 1, HALF + 31),
+            [HALF + 10, HALF + 20, HALF + 30]
+        );
+    }
+
+    #[test]
+    fn single_press_switches_once() {
+        let mut blinker = blinker(false);
+        blinker.button_mut().held = true;
+        let actions: Vec<u32> = (0..1_000)
+            .filter(|&now| blinker.step(now) == Some(Action::SwitchApp))
+            .collect();
+        assert_eq!(actions, [DEBOUNCE_MS]);
+    }
+
+    #[test]
+    fn press_step_leaves_led_alone() {
+        let mut blinker = blinker(false);
+        blinker.button_mut().held = true;
+        blinker.step(HALF - DEBOUNCE_MS);
+        let writes = blinker.led.writes;
+        // Due to toggle and debounced at the same step: the press wins
+        assert_eq!(blinker.step(HALF), Some(Action::SwitchApp));
+        assert_eq!(blinker.led.writes, writes);
+        blinker.step(HALF + 1);
+        assert!(blinker.led.high);
+    }
+
+    #[test]
+    fn bouncing_button_does_not_switch() {
+        let mut blinker = blinker(false);
+        for now in 0..1_000 {
+            // Changes level every 5 ms, never long enough to debounce
+            blinker.button_mut().held = now / 5 % 2 == 1;
+            assert_ne!(blinker.step(now), Some(Action::SwitchApp));
+        }
+    }
+
+    #[test]
+    fn held_button_keeps_blinking() {
+        let mut blinker = blinker(true);
+        assert_eq!(toggles(&mut blinker, 0, 2 * HALF + 1), [HALF, 2 * HALF]);
+    }
+}
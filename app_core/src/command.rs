@@ -0,0 +1,297 @@
+//! Framed, checksummed command protocol spoken over the UART
+//!
+//! Frame layout:
+//!
+//! ```text
+//! STX (0x02) | command | length | payload[length] | CRC8 | ETX (0x03)
+//! ```
+//!
+//! The CRC8 (poly 0x07, init 0x00, no reflection) covers command, length and
+//! payload. Multi-byte payload values are little-endian. Any malformed frame
+//! (bad CRC, missing ETX, wrong length for the command) is dropped and the
+//! parser goes back to hunting for the next STX.
+
+pub const STX: u8 = 0x02;
+pub const ETX: u8 = 0x03;
+
+/// Largest payload any command carries
+pub const MAX_PAYLOAD: usize = 8;
+
+// Command bytes
+const CMD_SET_DELAY: u8 = 0x01;
+const CMD_SWITCH_APP: u8 = 0x02;
+const CMD_STATUS: u8 = 0x03;
+const CMD_RESET_CAUSE: u8 = 0x04;
+const CMD_UPDATE_MODE: u8 = 0x05;
+const CMD_STACK_USAGE: u8 = 0x06;
+const CMD_PANICS: u8 = 0x07;
+const CMD_RESTART: u8 = 0x08;
+const CMD_LOCK: u8 = 0x09;
+const CMD_UNLOCK: u8 = 0x0A;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    /// Change the app's blink delay, payload: `u32` milliseconds
+    SetDelay(u32),
+    /// Switch to the other application
+    SwitchApp,
+    /// Print the app's current state
+    Status,
+    /// Print what caused the last reset (`reset?`)
+    ResetCause,
+    /// Reboot into the bootloader's update mode
+    UpdateMode,
+    /// Print the stack high-water mark
+    StackUsage,
+    /// Print the panics kept in the noinit ring (`panics?`)
+    Panics,
+    /// Reset and come back to the same app
+    Restart,
+    /// Ignore switch requests until `Unlock`
+    Lock,
+    Unlock,
+}
+
+impl Command {
+    fn decode(command: u8, payload: &[u8]) -> Option<Self> {
+        match (command, payload) {
+            (CMD_SET_DELAY, &[a, b, c, d]) => {
+                Some(Command::SetDelay(u32::from_le_bytes([a, b, c, d])))
+            }
+            (CMD_SWITCH_APP, &[]) => Some(Command::SwitchApp),
+            (CMD_STATUS, &[]) => Some(Command::Status),
+            (CMD_RESET_CAUSE, &[]) => Some(Command::ResetCause),
+            (CMD_UPDATE_MODE, &[]) => Some(Command::UpdateMode),
+            (CMD_STACK_USAGE, &[]) => Some(Command::StackUsage),
+            (CMD_PANICS, &[]) => Some(Command::Panics),
+            (CMD_RESTART, &[]) => Some(Command::Restart),
+            (CMD_LOCK, &[]) => Some(Command::Lock),
+            (CMD_UNLOCK, &[]) => Some(Command::Unlock),
+            _ => None,
+        }
+    }
+}
+
+/// CRC-8 with polynomial 0x07 (CRC-8/SMBUS)
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &byte| crc8_update(crc, byte))
+}
+
+fn crc8_update(crc: u8, byte: u8) -> u8 {
+    let mut crc = crc ^ byte;
+    for _ in 0..8 {
+        crc = if crc & 0x80 != 0 {
+            (crc << 1) ^ 0x07
+        } else {
+            crc << 1
+        };
+    }
+    crc
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    WaitStx,
+    Command,
+    Length,
+    Payload,
+    Crc,
+    WaitEtx,
+}
+
+/// Byte-at-a-time frame decoder
+pub struct CommandParser {
+    state: State,
+    command: u8,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+    received: usize,
+    crc: u8,
+}
+
+impl CommandParser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::WaitStx,
+            command: 0,
+            len: 0,
+            payload: [0; MAX_PAYLOAD],
+            received: 0,
+            crc: 0,
+        }
+    }
+
+    /// Feeds one received byte, returns a command once a valid frame completes
+    pub fn feed(&mut self, byte: u8) -> Option<Command> {
+        match self.state {
+            State::WaitStx => {
+                if byte == STX {
+                    self.crc = 0;
+                    self.received = 0;
+                    self.state = State::Command;
+                }
+            }
+            State::Command => {
+                self.command = byte;
+                self.crc = crc8_update(self.crc, byte);
+                self.state = State::Length;
+            }
+            State::Length => {
+                self.len = byte as usize;
+                self.crc = crc8_update(self.crc, byte);
+                self.state = match self.len {
+                    0 => State::Crc,
+                    n if n <= MAX_PAYLOAD => State::Payload,
+                    _ => State::WaitStx,
+                };
+            }
+            State::Payload => {
+                self.payload[self.received] = byte;
+                self.received += 1;
+                self.crc = crc8_update(self.crc, byte);
+                if self.received == self.len {
+                    self.state = State::Crc;
+                }
+            }
+            State::Crc => {
+                self.state = if byte == self.crc {
+                    State::WaitEtx
+                } else {
+                    State::WaitStx
+                };
+            }
+            State::WaitEtx => {
+                self.state = State::WaitStx;
+                if byte == ETX {
+                    return Command::decode(self.command, &self.payload[..self.len]);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Default for CommandParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A complete frame for `command` carrying `payload`
+    fn frame(command: u8, payload: &[u8]) -> Vec<u8> {
+        let mut body = vec![command, payload.len() as u8];
+        body.extend_from_slice(payload);
+        let crc = crc8(&body);
+
+        let mut frame = vec![STX];
+        frame.extend(body);
+        frame.extend([crc, ETX]);
+        frame
+    }
+
+    /// Every command the parser completes while fed `bytes`
+    fn parse(parser: &mut CommandParser, bytes: &[u8]) -> Vec<Command> {
+        bytes.iter().filter_map(|&byte| parser.feed(byte)).collect()
+    }
+
+    #[test]
+    fn crc8_check_value() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc8(&[]), 0);
+    }
+
+    #[test]
+    fn good_frames() {
+        let mut parser = CommandParser::new();
+        let delay = frame(CMD_SET_DELAY, &500u32.to_le_bytes());
+        assert_eq!(parse(&mut parser, &delay), [Command::SetDelay(500)]);
+        // The documented restart frame
+        assert_eq!(
+            parse(&mut parser, &[0x02, 0x08, 0x00, 0xA8, 0x03]),
+            [Command::Restart]
+        );
+    }
+
+    #[test]
+    fn command_only_on_etx() {
+        let mut parser = CommandParser::new();
+        let status = frame(CMD_STATUS, &[]);
+        let (last, rest) = status.split_last().unwrap();
+        assert!(parse(&mut parser, rest).is_empty());
+        assert_eq!(parser.feed(*last), Some(Command::Status));
+    }
+
+    #[test]
+    fn bad_crc_is_dropped() {
+        let mut parser = CommandParser::new();
+        let mut status = frame(CMD_STATUS, &[]);
+        status[3] ^= 0x01;
+        assert!(parse(&mut parser, &status).is_empty());
+        assert_eq!(
+            parse(&mut parser, &frame(CMD_STATUS, &[])),
+            [Command::Status]
+        );
+    }
+
+    #[test]
+    fn missing_etx_is_dropped() {
+        let mut parser = CommandParser::new();
+        let mut status = frame(CMD_STATUS, &[]);
+        *status.last_mut().unwrap() = 0x00;
+        assert!(parse(&mut parser, &status).is_empty());
+        assert_eq!(
+            parse(&mut parser, &frame(CMD_STATUS, &[])),
+            [Command::Status]
+        );
+    }
+
+    #[test]
+    fn oversize_length_is_dropped() {
+        let mut parser = CommandParser::new();
+        let payload = [0x55; MAX_PAYLOAD + 1];
+        assert!(parse(&mut parser, &frame(CMD_SET_DELAY, &payload)).is_empty());
+        // Back to hunting for STX straight after the length byte
+        assert_eq!(parse(&mut parser, &frame(CMD_LOCK, &[])), [Command::Lock]);
+    }
+
+    #[test]
+    fn wrong_length_for_command_is_dropped() {
+        let mut parser = CommandParser::new();
+        assert!(parse(&mut parser, &frame(CMD_SET_DELAY, &[1, 2])).is_empty());
+        assert!(parse(&mut parser, &frame(CMD_STATUS, &[1])).is_empty());
+        assert!(parse(&mut parser, &frame(0x7F, &[])).is_empty());
+    }
+
+    #[test]
+    fn resyncs_after_garbage() {
+        let mut parser = CommandParser::new();
+        let mut bytes = vec![0xFF, 0x00, 0x41, ETX, 0x10];
+        bytes.extend(frame(CMD_UNLOCK, &[]));
+        assert_eq!(parse(&mut parser, &bytes), [Command::Unlock]);
+
+        // A stray STX starts a frame that fails its CRC, the next one parses
+        let mut bytes = vec![STX, 0x33, 0x00, 0x00, ETX];
+        bytes.extend(frame(CMD_PANICS, &[]));
+        assert_eq!(parse(&mut parser, &bytes), [Command::Panics]);
+    }
+
+    #[test]
+    fn frames_back_to_back() {
+        let mut parser = CommandParser::new();
+        let mut bytes = frame(CMD_SWITCH_APP, &[]);
+        bytes.extend(frame(CMD_SET_DELAY, &100u32.to_le_bytes()));
+        bytes.extend(frame(CMD_UPDATE_MODE, &[]));
+        assert_eq!(
+            parse(&mut parser, &bytes),
+            [
+                Command::SwitchApp,
+                Command::SetDelay(100),
+                Command::UpdateMode
+            ]
+        );
+    }
+}
@@ -0,0 +1,27 @@
+//! Minimal trait over the apps' LED
+//!
+//! App logic written against it instead of a concrete HAL pin doesn't care
+//! what drives it, so it can run against simple stand-ins off-target. The
+//! button side uses embedded-hal's `InputPin` directly, see [`AppSwitcher`].
+//!
+//! [`AppSwitcher`]: crate::AppSwitcher
+
+use embedded_hal::digital::OutputPin;
+
+pub trait Led {
+    fn on(&mut self);
+    fn off(&mut self);
+}
+
+/// `on` drives the pin high, which is what the apps' blink loops have always
+/// started with; the absolute polarity doesn't matter for a blink. A pin that
+/// fails to switch is left as it is.
+impl<P: OutputPin> Led for P {
+    fn on(&mut self) {
+        self.set_high().ok();
+    }
+
+    fn off(&mut self) {
+        self.set_low().ok();
+    }
+}
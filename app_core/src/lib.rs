@@ -0,0 +1,20 @@
+//! Hardware-free logic of the applications
+//!
+//! The UART command framing, the debounced app switch button and App2's
+//! blinker. They work on plain bytes, embedded-hal pins and a millisecond
+//! count handed in; `app_common` wires them to the peripherals and
+//! re-exports them, so the apps keep using them from there. Like
+//! `bootloader_core` the crate builds for the host, where its unit tests
+//! run: `cargo test -p app_core --target x86_64-unknown-linux-gnu`.
+
+#![cfg_attr(not(test), no_std)]
+
+mod blinker;
+mod command;
+mod io;
+mod switcher;
+
+pub use blinker::{Action, Blinker};
+pub use command::{crc8, Command, CommandParser, ETX, MAX_PAYLOAD, STX};
+pub use io::Led;
+pub use switcher::{AppSwitcher, DEBOUNCE_MS};
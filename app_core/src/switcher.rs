@@ -0,0 +1,199 @@
+//! Debounced "switch to the other app" button, over any `InputPin`
+//!
+//! [`AppSwitcher`] is polled with a free-running millisecond clock. A press
+//! counts once the pin has held its new level for [`DEBOUNCE_MS`], and only
+//! on the way in: holding the button doesn't repeat, and a button already
+//! held when the switcher is created doesn't count. Polarity follows the
+//! `button-active-low` feature, which `app_common`'s feature of the same name
+//! turns on, so it agrees with `app_common::button`.
+
+use embedded_hal::digital::InputPin;
+
+/// How long the pin has to keep a new level before it is believed
+pub const DEBOUNCE_MS: u32 = 20;
+
+pub struct AppSwitcher<B> {
+    button: B,
+    /// Debounced state
+    pressed: bool,
+    /// Last raw reading and when it was first seen
+    raw: bool,
+    raw_since_ms: u32,
+}
+
+impl<B: InputPin> AppSwitcher<B> {
+    /// Takes the button's current state as the baseline
+    pub fn new(mut button: B) -> Self {
+        let pressed = read(&mut button);
+        Self {
+            button,
+            pressed,
+            raw: pressed,
+            raw_since_ms: 0,
+        }
+    }
+
+    /// The button, e.g. to wait on its EXTI line
+    pub fn button_mut(&mut self) -> &mut B {
+        &mut self.button
+    }
+
+    /// Samples the button at `now_ms` (wrapping), `true` on a debounced press
+    pub fn poll_press(&mut self, now_ms: u32) -> bool {
+        let raw = read(&mut self.button);
+        if raw != self.raw {
+            self.raw = raw;
+            self.raw_since_ms = now_ms;
+            return false;
+        }
+        if raw == self.pressed || now_ms.wrapping_sub(self.raw_since_ms) < DEBOUNCE_MS {
+            return false;
+        }
+        self.pressed = raw;
+        raw
+    }
+}
+
+/// Whether the button is held; a pin that can't be read counts as released
+fn read<B: InputPin>(button: &mut B) -> bool {
+    let level = if cfg!(feature = "button-active-low") {
+        button.is_low()
+    } else {
+        button.is_high()
+    };
+    level.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_hal::digital::{Error, ErrorKind, ErrorType};
+
+    /// A button pin whose level the test sets
+    struct MockPin {
+        high: bool,
+    }
+
+    /// The pin level of a held (or released) button, for either polarity
+    fn level(held: bool) -> bool {
+        held != cfg!(feature = "button-active-low")
+    }
+
+    impl MockPin {
+        fn new(held: bool) -> Self {
+            Self { high: level(held) }
+        }
+    }
+
+    impl ErrorType for MockPin {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockPin {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(self.high)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(!self.high)
+        }
+    }
+
+    fn set(switcher: &mut AppSwitcher<MockPin>, held: bool) {
+        switcher.button_mut().high = level(held);
+    }
+
+    /// Polls every millisecond in `from..to`, returning the times a press
+    /// was reported
+    fn presses(switcher: &mut AppSwitcher<MockPin>, from: u32, to: u32) -> Vec<u32> {
+        (from..to).filter(|&now| switcher.poll_press(now)).collect()
+    }
+
+    #[test]
+    fn press_counts_once_debounced() {
+        let mut switcher = AppSwitcher::new(MockPin::new(false));
+        assert!(presses(&mut switcher, 0, 10).is_empty());
+
+        set(&mut switcher, true);
+        assert_eq!(presses(&mut switcher, 10, 100), [10 + DEBOUNCE_MS]);
+    }
+
+    #[test]
+    fn bounces_shorter_than_debounce_are_ignored() {
+        let mut switcher = AppSwitcher::new(MockPin::new(false));
+        for start in (0..200).step_by(DEBOUNCE_MS as usize) {
+            set(&mut switcher, true);
+            assert!(presses(&mut switcher, start, start + DEBOUNCE_MS / 2).is_empty());
+            set(&mut switcher, false);
+            assert!(
+                presses(&mut switcher, start + DEBOUNCE_MS / 2, start + DEBOUNCE_MS).is_empty()
+            );
+        }
+    }
+
+    #[test]
+    fn held_button_does_not_repeat() {
+        let mut switcher = AppSwitcher::new(MockPin::new(false));
+        set(&mut switcher, true);
+        assert_eq!(presses(&mut switcher, 0, 5_000).len(), 1);
+
+        // Released, then pressed again: a second press
+        set(&mut switcher, false);
+        assert!(presses(&mut switcher, 5_000, 5_100).is_empty());
+        set(&mut switcher, true);
+        assert_eq!(presses(&mut switcher, 5_100, 5_200), [5_100 + DEBOUNCE_MS]);
+    }
+
+    #[test]
+    fn held_at_start_is_not_a_press() {
+        let mut switcher = AppSwitcher::new(MockPin::new(true));
+        assert!(presses(&mut switcher, 0, 1_000).is_empty());
+
+        set(&mut switcher, false);
+        assert!(presses(&mut switcher, 1_000, 1_100).is_empty());
+        set(&mut switcher, true);
+        assert_eq!(presses(&mut switcher, 1_100, 1_200).len(), 1);
+    }
+
+    #[test]
+    fn debounce_across_clock_wrap() {
+        let mut switcher = AppSwitcher::new(MockPin::new(false));
+        let start = u32::MAX - 5;
+        set(&mut switcher, true);
+        assert!(!switcher.poll_press(start));
+        assert!(!switcher.poll_press(start.wrapping_add(DEBOUNCE_MS - 1)));
+        assert!(switcher.poll_press(start.wrapping_add(DEBOUNCE_MS)));
+    }
+
+    #[derive(Debug)]
+    struct ReadFailed;
+
+    impl Error for ReadFailed {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    struct BrokenPin;
+
+    impl ErrorType for BrokenPin {
+        type Error = ReadFailed;
+    }
+
+    impl InputPin for BrokenPin {
+        fn is_high(&mut self) -> Result<bool, ReadFailed> {
+            Err(ReadFailed)
+        }
+
+        fn is_low(&mut self) -> Result<bool, ReadFailed> {
+            Err(ReadFailed)
+        }
+    }
+
+    #[test]
+    fn unreadable_pin_is_released() {
+        let mut switcher = AppSwitcher::new(BrokenPin);
+        assert!((0..1_000).all(|now| !switcher.poll_press(now)));
+    }
+}
@@ -0,0 +1,38 @@
+//! Generates the address constants (`APP1_ADDR`, `MAGIC_ADDR`, ...) from the
+//! workspace's `layout.toml`, the same file the images' `memory.x` come from
+
+#[path = "../build-support/layout.rs"]
+mod layout;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    let layout = layout::Layout::load();
+    let consts = [
+        ("BOOTLOADER_ADDR", layout.bootloader.origin),
+        ("BOOTLOADER_SIZE", layout.bootloader.size),
+        ("SETTINGS_LOG_ADDR", layout.settings.origin),
+        ("SETTINGS_LOG_SIZE", layout.settings.size),
+        ("APP1_ADDR", layout.app1.origin),
+        ("APP1_SIZE", layout.app1.size),
+        ("APP2_ADDR", layout.app2.origin),
+        ("APP2_SIZE", layout.app2.size),
+        ("RAM_START", layout.ram.origin),
+        ("RAM_END", layout.ram.end()),
+        ("MAGIC_ADDR", layout.magic_addr()),
+        ("HEARTBEAT_ADDR", layout.heartbeat_addr()),
+        ("PANIC_LOG_ADDR", layout.panic_log_addr()),
+        ("PANIC_LOG_SIZE", layout.panic_log),
+        ("STACK_TOP", layout.image_ram().end()),
+        ("STACK_GUARD", layout.stack_guard),
+    ];
+
+    let mut code = String::from("// Generated by build.rs from layout.toml\n");
+    for (name, value) in consts {
+        code += &format!("pub const {name}: u32 = {value:#010X};\n");
+    }
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("layout.rs"), code).unwrap();
+}
@@ -0,0 +1,95 @@
+//! Image header placed in every application slot
+//!
+//! The header sits at a fixed offset just past the vector table, so the
+//! bootloader can find it without parsing the image. Apps emit it with
+//! [`slot_header!`](crate::slot_header) and the linker reserves
+//! [`HEADER_SPACE`] bytes for it; `image_len`, `crc32` and `signature` are
+//! left at zero by the build and stamped in afterwards by `cargo xtask image`.
+
+/// Offset of the header from the start of a slot (F411 vector table is 0x198 bytes)
+pub const HEADER_OFFSET: u32 = 0x200;
+
+/// Flash reserved for the header, `.text` starts right after it
+pub const HEADER_SPACE: u32 = 0x100;
+
+/// Marks a valid header: "SLOT" in little-endian ASCII
+pub const HEADER_MAGIC: u32 = 0x544F_4C53;
+
+/// Metadata describing the image stored in a slot
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SlotHeader {
+    /// Always `HEADER_MAGIC`
+    pub magic: u32,
+    /// Image version, see [`pack_version`]
+    pub version: u32,
+    /// Bytes from the slot base covered by `crc32`, 0 until stamped
+    pub image_len: u32,
+    /// CRC-32 (IEEE) of the image with the header itself skipped
+    pub crc32: u32,
+    /// Ed25519ph signature over the same bytes as `crc32` followed by
+    /// `version` and `image_len`, all zero if unsigned
+    pub signature: [u8; 64],
+}
+
+impl SlotHeader {
+    /// Header as emitted by the build, before length and CRC are known
+    pub const fn unstamped(version: u32) -> Self {
+        Self {
+            magic: HEADER_MAGIC,
+            version,
+            image_len: 0,
+            crc32: 0,
+            signature: [0; 64],
+        }
+    }
+
+    /// Whether `cargo xtask image` has filled in length and CRC
+    pub const fn is_stamped(&self) -> bool {
+        self.image_len != 0
+    }
+}
+
+/// Packs a semantic version as `major.minor.patch` into one word
+pub const fn pack_version(major: u8, minor: u8, patch: u8) -> u32 {
+    ((major as u32) << 16) | ((minor as u32) << 8) | patch as u32
+}
+
+/// Splits a packed version back into `(major, minor, patch)`
+pub const fn unpack_version(version: u32) -> (u8, u8, u8) {
+    ((version >> 16) as u8, (version >> 8) as u8, version as u8)
+}
+
+/// Parses a decimal version component at compile time
+///
+/// Used on `CARGO_PKG_VERSION_*`; saturates at 255.
+pub const fn parse_u8(s: &str) -> u8 {
+    let bytes = s.as_bytes();
+    let mut value: u32 = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = value * 10 + (bytes[i] - b'0') as u32;
+        if value > u8::MAX as u32 {
+            return u8::MAX;
+        }
+        i += 1;
+    }
+    value as u8
+}
+
+/// Emits the slot header for the calling crate, versioned from its `Cargo.toml`
+///
+/// The app's `sections.x` must place `.slot_header` at `ORIGIN(FLASH) + HEADER_OFFSET`.
+#[macro_export]
+macro_rules! slot_header {
+    () => {
+        #[link_section = ".slot_header"]
+        #[used]
+        static SLOT_HEADER: $crate::SlotHeader =
+            $crate::SlotHeader::unstamped($crate::pack_version(
+                $crate::parse_u8(env!("CARGO_PKG_VERSION_MAJOR")),
+                $crate::parse_u8(env!("CARGO_PKG_VERSION_MINOR")),
+                $crate::parse_u8(env!("CARGO_PKG_VERSION_PATCH")),
+            ));
+    };
+}
@@ -0,0 +1,50 @@
+//! Proof of life from the running app
+//!
+//! Two noinit words right below the boot magic: a counter the app bumps from
+//! its main loop (`heartbeat_tick`) and the slot the bootloader last jumped
+//! into, stored as that slot's boot magic. The bootloader zeroes the counter
+//! and records the slot just before the jump; on the next entry a counter
+//! still at zero means the app never got as far as its loop.
+
+use crate::{BootTarget, NoinitCell, HEARTBEAT_ADDR};
+
+fn counter() -> &'static NoinitCell<u32> {
+    // HEARTBEAT_ADDR is reserved by every image's linker script
+    unsafe { NoinitCell::at(HEARTBEAT_ADDR) }
+}
+
+fn armed() -> &'static NoinitCell<u32> {
+    unsafe { NoinitCell::at(HEARTBEAT_ADDR + 4) }
+}
+
+/// Bumps the heartbeat counter, called periodically by the running app
+///
+/// Never lands back on zero, which is what the bootloader arms it with.
+pub fn heartbeat_tick() {
+    let count = counter().read();
+    counter().write(count.wrapping_add(1).max(1));
+}
+
+/// Current value of the heartbeat counter
+pub fn heartbeat_count() -> u32 {
+    counter().read()
+}
+
+/// Starts watching `target`, called by the bootloader right before the jump
+pub fn arm_heartbeat(target: BootTarget) {
+    counter().write(0);
+    armed().write(target.magic());
+}
+
+/// Slot the heartbeat was last armed for
+///
+/// `None` if nothing was armed since the last `disarm_heartbeat`. RAM powers
+/// up with anything in it, so after a power-on this can name a slot too.
+pub fn armed_heartbeat() -> Option<BootTarget> {
+    BootTarget::from_magic(armed().read())
+}
+
+/// Forgets the armed slot
+pub fn disarm_heartbeat() {
+    armed().write(0);
+}
@@ -0,0 +1,42 @@
+//! Bootloader identification block at a fixed flash address
+//!
+//! Lets host tooling (over SWD, or through the update protocol) find out
+//! which bootloader it is talking to without knowing how it was built. The
+//! bootloader's linker script (`sections.x`) places it right after its vector table, at
+//! [`BOOTLOADER_INFO_ADDR`].
+
+/// Where the bootloader keeps its [`BootloaderInfo`]
+pub const BOOTLOADER_INFO_ADDR: u32 = crate::BOOTLOADER_ADDR + 0x200;
+
+/// Marks a valid info block: "BOOT" in little-endian ASCII
+pub const INFO_MAGIC: u32 = 0x544F_4F42;
+
+/// Revision of the app <-> bootloader handshake and update protocol,
+/// bumped whenever either changes incompatibly
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Bits of `BootloaderInfo::features`
+/// Boot magic kept in `RTC_BKP0R` instead of noinit RAM
+pub const FEATURE_RTC_BACKUP: u32 = 1 << 0;
+/// Only Ed25519-signed images are booted
+pub const FEATURE_SECURE_BOOT: u32 = 1 << 1;
+/// `MAGIC_UPDATE` enters the UART update mode
+pub const FEATURE_UPDATE_MODE: u32 = 1 << 2;
+/// `MAGIC_SELF_TEST` runs the slot self-test
+pub const FEATURE_SELF_TEST: u32 = 1 << 3;
+
+/// Version and capabilities of the bootloader
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BootloaderInfo {
+    /// Always `INFO_MAGIC`
+    pub magic: u32,
+    /// See [`PROTOCOL_VERSION`]
+    pub protocol_version: u32,
+    /// Bootloader crate version, see [`pack_version`](crate::pack_version)
+    pub version: u32,
+    /// Largest image any slot accepts, in bytes
+    pub max_image_size: u32,
+    /// `FEATURE_*` bits
+    pub features: u32,
+}
@@ -0,0 +1,309 @@
+//! Boot handshake shared by the bootloader and the applications
+//!
+//! An application asks for a specific image by storing a magic value somewhere
+//! that survives a system reset and then resetting the chip. The bootloader
+//! reads that value on its way up, clears it and jumps to the matching slot
+//! (or, for [`BootRequest::UpdateMode`], stays resident to take an update).
+//!
+//! Where the magic lives is abstracted by [`MagicStore`], so the decision logic
+//! does not care whether it is noinit RAM or the RTC backup registers.
+
+#![no_std]
+
+mod header;
+mod heartbeat;
+mod info;
+mod noinit;
+mod reset_cause;
+mod roles;
+
+pub use header::{
+    pack_version, parse_u8, unpack_version, SlotHeader, HEADER_MAGIC, HEADER_OFFSET, HEADER_SPACE,
+};
+pub use heartbeat::{
+    arm_heartbeat, armed_heartbeat, disarm_heartbeat, heartbeat_count, heartbeat_tick,
+};
+pub use info::{
+    BootloaderInfo, BOOTLOADER_INFO_ADDR, FEATURE_RTC_BACKUP, FEATURE_SECURE_BOOT,
+    FEATURE_SELF_TEST, FEATURE_UPDATE_MODE, INFO_MAGIC, PROTOCOL_VERSION,
+};
+pub use noinit::NoinitCell;
+pub use reset_cause::{stash_reset_cause, stashed_reset_cause, ResetCause, RESET_CAUSE_ADDR};
+pub use roles::{SlotRoles, SETTINGS_ADDR, SETTINGS_ROLES_SWAPPED, SETTINGS_TAG};
+
+use core::ptr::{read_volatile, write_volatile};
+
+// Magic values for app selection
+pub const MAGIC_APP1: u32 = 0xDEAD_BEEF;
+pub const MAGIC_APP2: u32 = 0xCAFE_BABE;
+
+/// Asks the bootloader to run its self-test instead of booting an app
+pub const MAGIC_SELF_TEST: u32 = 0x5E1F_7E57;
+
+/// Asks the bootloader to stay resident and listen for updates on the UART
+pub const MAGIC_UPDATE: u32 = 0xB007_10AD;
+
+// Flash and RAM layout from layout.toml, the same numbers every image's
+// memory.x is generated from: the bootloader and slot bases and sizes
+// (BOOTLOADER_ADDR, APP1_ADDR, APP1_SIZE, ...), the bootloader's settings
+// log at the top of its region (SETTINGS_LOG_ADDR/SETTINGS_LOG_SIZE, left out
+// of its image), the SRAM bounds (RAM_START, RAM_END), the magic word heading
+// the bootloader's `NOINIT_RAM` region (MAGIC_ADDR), and below it the app
+// heartbeat (HEARTBEAT_ADDR) and the apps' panic ring
+// (`app_common::PanicLog`, PANIC_LOG_ADDR/PANIC_LOG_SIZE).
+// Every image's linker script leaves those out of RAM, and its stack starts
+// at STACK_TOP, STACK_GUARD bytes further down.
+include!(concat!(env!("OUT_DIR"), "/layout.rs"));
+
+// The handshake words have to be real, word aligned RAM, stacked in the
+// documented order and clear of the stack with the guard in between. A
+// layout.toml that breaks this doesn't build
+const _: () = assert!(MAGIC_ADDR >= RAM_START && MAGIC_ADDR + 8 <= RAM_END);
+const _: () = assert!(
+    MAGIC_ADDR.is_multiple_of(4)
+        && HEARTBEAT_ADDR.is_multiple_of(4)
+        && PANIC_LOG_ADDR.is_multiple_of(4)
+);
+const _: () = assert!(HEARTBEAT_ADDR + 8 <= MAGIC_ADDR);
+const _: () = assert!(PANIC_LOG_ADDR + PANIC_LOG_SIZE <= HEARTBEAT_ADDR);
+const _: () = assert!(STACK_GUARD > 0 && STACK_TOP + STACK_GUARD <= PANIC_LOG_ADDR);
+
+/// Application images the bootloader knows how to start
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BootTarget {
+    App1,
+    App2,
+}
+
+impl BootTarget {
+    /// Every slot, in boot order
+    pub const ALL: [BootTarget; 2] = [BootTarget::App1, BootTarget::App2];
+
+    /// Magic value that selects this target
+    pub const fn magic(self) -> u32 {
+        match self {
+            BootTarget::App1 => MAGIC_APP1,
+            BootTarget::App2 => MAGIC_APP2,
+        }
+    }
+
+    /// Flash address of the target's vector table
+    pub const fn addr(self) -> u32 {
+        match self {
+            BootTarget::App1 => APP1_ADDR,
+            BootTarget::App2 => APP2_ADDR,
+        }
+    }
+
+    /// Size of the target's flash slot
+    pub const fn size(self) -> u32 {
+        match self {
+            BootTarget::App1 => APP1_SIZE,
+            BootTarget::App2 => APP2_SIZE,
+        }
+    }
+
+    /// The slot whose flash range contains `addr`
+    pub fn containing(addr: u32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|target| (target.addr()..target.addr() + target.size()).contains(&addr))
+    }
+
+    /// Sanity check of the slot's vector table
+    ///
+    /// The initial stack pointer has to point into SRAM and the reset vector
+    /// has to be a Thumb address inside the slot. This does not prove the
+    /// image is intact, only that jumping into it won't immediately fault.
+    pub fn has_valid_vectors(self) -> bool {
+        let base = self.addr();
+        let (sp, reset) = unsafe {
+            (
+                read_volatile(base as *const u32),
+                read_volatile((base + 4) as *const u32),
+            )
+        };
+        self.vectors_ok(sp, reset)
+    }
+
+    /// The check behind `has_valid_vectors`, on an already read stack
+    /// pointer and reset vector
+    pub fn vectors_ok(self, sp: u32, reset: u32) -> bool {
+        let base = self.addr();
+        let sp_ok = (RAM_START..=RAM_END).contains(&sp) && sp.is_multiple_of(4);
+        let reset_ok = reset & 1 == 1 && (base..base + self.size()).contains(&(reset & !1));
+        sp_ok && reset_ok
+    }
+
+    /// Decodes a stored magic value, `None` if it doesn't name a target
+    pub const fn from_magic(magic: u32) -> Option<Self> {
+        match magic {
+            MAGIC_APP1 => Some(BootTarget::App1),
+            MAGIC_APP2 => Some(BootTarget::App2),
+            _ => None,
+        }
+    }
+}
+
+/// Everything a stored magic value can ask the bootloader for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BootRequest {
+    /// Boot the given application
+    App(BootTarget),
+    /// Stay in the bootloader and run the update listener
+    UpdateMode,
+    /// Check both slots and report instead of booting
+    SelfTest,
+}
+
+impl BootRequest {
+    /// Magic value that encodes this request
+    pub const fn magic(self) -> u32 {
+        match self {
+            BootRequest::App(target) => target.magic(),
+            BootRequest::UpdateMode => MAGIC_UPDATE,
+            BootRequest::SelfTest => MAGIC_SELF_TEST,
+        }
+    }
+
+    /// Decodes a stored magic value, `None` if it isn't a known request
+    pub const fn from_magic(magic: u32) -> Option<Self> {
+        match magic {
+            MAGIC_UPDATE => Some(BootRequest::UpdateMode),
+            MAGIC_SELF_TEST => Some(BootRequest::SelfTest),
+            _ => match BootTarget::from_magic(magic) {
+                Some(target) => Some(BootRequest::App(target)),
+                None => None,
+            },
+        }
+    }
+}
+
+impl From<BootTarget> for BootRequest {
+    fn from(target: BootTarget) -> Self {
+        BootRequest::App(target)
+    }
+}
+
+/// Storage for the boot magic that survives a system reset
+pub trait MagicStore {
+    fn read(&self) -> u32;
+    fn write(&mut self, v: u32);
+    fn clear(&mut self);
+}
+
+/// Magic kept in uninitialized RAM
+///
+/// Works because SRAM is not cleared by a software reset and neither the
+/// bootloader's runtime nor the apps touch the `.noinit` word on startup.
+pub struct NoinitStore {
+    cell: &'static NoinitCell<u32>,
+}
+
+impl NoinitStore {
+    /// Store backed by a noinit word, e.g. the bootloader's `.noinit` static
+    pub const fn new(cell: &'static NoinitCell<u32>) -> Self {
+        Self { cell }
+    }
+
+    /// Store at the fixed handshake address, usable from any image
+    pub const fn shared() -> Self {
+        // MAGIC_ADDR is reserved by the bootloader's linker script
+        Self::new(unsafe { NoinitCell::at(MAGIC_ADDR) })
+    }
+}
+
+impl MagicStore for NoinitStore {
+    fn read(&self) -> u32 {
+        self.cell.read()
+    }
+
+    fn write(&mut self, v: u32) {
+        self.cell.write(v)
+    }
+
+    fn clear(&mut self) {
+        self.cell.write(0);
+    }
+}
+
+/// Magic kept in the RTC backup register `RTC_BKP0R`
+///
+/// The backup registers sit in the backup domain, so they survive every reset
+/// except a backup domain reset and don't reserve any RAM.
+#[cfg(feature = "rtc-backup")]
+pub struct RtcBackupStore {
+    _private: (),
+}
+
+#[cfg(feature = "rtc-backup")]
+impl RtcBackupStore {
+    // RTC base 0x4000_2800, BKP0R at offset 0x50
+    const BKP0R: *mut u32 = 0x4000_2850 as *mut u32;
+
+    /// # Safety
+    /// Writes are silently dropped unless the PWR clock is on and backup
+    /// domain write access (`PWR_CR.DBP`) has been enabled beforehand
+    pub const unsafe fn new() -> Self {
+        Self { _private: () }
+    }
+}
+
+/// Turns on the PWR clock and unlocks writes to the backup domain
+///
+/// Needed once per boot before `RtcBackupStore` (or any other backup
+/// register, or the RTC itself) can be written. Leaves the rest of the RTC
+/// alone, so an RTC configured by an app keeps running.
+///
+/// # Safety
+/// Read-modify-writes `RCC_APB1ENR` and `PWR_CR` behind the HAL's back; call it
+/// while nothing else is reconfiguring those registers (e.g. early in init)
+pub unsafe fn enable_backup_access() {
+    const RCC_APB1ENR: *mut u32 = 0x4002_3840 as *mut u32;
+    const RCC_APB1ENR_PWREN: u32 = 1 << 28;
+    const PWR_CR: *mut u32 = 0x4000_7000 as *mut u32;
+    const PWR_CR_DBP: u32 = 1 << 8;
+
+    write_volatile(RCC_APB1ENR, read_volatile(RCC_APB1ENR) | RCC_APB1ENR_PWREN);
+    // The peripheral needs a couple of cycles after its clock is enabled
+    cortex_m::asm::dsb();
+    write_volatile(PWR_CR, read_volatile(PWR_CR) | PWR_CR_DBP);
+}
+
+#[cfg(feature = "rtc-backup")]
+impl MagicStore for RtcBackupStore {
+    fn read(&self) -> u32 {
+        unsafe { read_volatile(Self::BKP0R) }
+    }
+
+    fn write(&mut self, v: u32) {
+        unsafe { write_volatile(Self::BKP0R, v) }
+    }
+
+    fn clear(&mut self) {
+        self.write(0);
+    }
+}
+
+/// Asks the bootloader for `target` and resets into it
+///
+/// Stores the request's magic, makes sure the write has landed and triggers a
+/// system reset. The bootloader picks the value up on its way back. Takes a
+/// plain [`BootTarget`] too, as well as [`BootRequest::UpdateMode`].
+#[cfg(not(feature = "standalone"))]
+pub fn request_boot<S: MagicStore>(store: &mut S, target: impl Into<BootRequest>) -> ! {
+    store.write(target.into().magic());
+
+    // Memory barrier
+    cortex_m::asm::dsb();
+
+    // Trigger system reset using cortex-m API
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// Standalone builds have no bootloader to honour the magic, and no other
+/// app to go to, so a boot request simply restarts the running image
+#[cfg(feature = "standalone")]
+pub fn request_boot<S: MagicStore>(_store: &mut S, _target: impl Into<BootRequest>) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
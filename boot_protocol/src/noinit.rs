@@ -0,0 +1,68 @@
+//! Values in RAM that the runtime never initialises
+//!
+//! SRAM keeps its contents across a system reset, so a word that is left out
+//! of `.bss`/`.data` initialisation carries information from one image to the
+//! next. [`NoinitCell`] holds the access rules for such words in one place:
+//! every access is volatile (the compiler must not assume it knows the
+//! contents, which another image wrote) and writes are followed by a DSB, so
+//! they have landed before a reset that may follow.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr::{read_volatile, write_volatile};
+
+/// A value that survives resets, e.g. in a `#[link_section = ".noinit"]` static
+///
+/// Meant for plain words: `T` must be valid for any bit pattern, since the
+/// first read after power-up sees whatever the RAM powered up with.
+#[repr(transparent)]
+pub struct NoinitCell<T: Copy> {
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Only ever accessed whole and volatile; the images using it are single-core
+// and don't share a cell between interrupt priorities
+unsafe impl<T: Copy + Send> Sync for NoinitCell<T> {}
+
+impl<T: Copy> NoinitCell<T> {
+    /// A cell for a `.noinit` static
+    ///
+    /// # Safety
+    /// The static has to be placed in a section the runtime leaves alone
+    /// (`.noinit` in the bootloader's `memory.x`) and `T` has to be valid for
+    /// any bit pattern
+    pub const unsafe fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// The cell at a fixed address, for words another image's linker script
+    /// reserves (see `MAGIC_ADDR`)
+    ///
+    /// # Safety
+    /// `addr` must be aligned for `T`, in RAM, never initialised by the
+    /// running image's runtime, and `T` has to be valid for any bit pattern
+    pub const unsafe fn at(addr: u32) -> &'static Self {
+        &*(addr as *const Self)
+    }
+
+    pub fn read(&self) -> T {
+        unsafe { read_volatile(self.value.get().cast::<T>()) }
+    }
+
+    pub fn write(&self, value: T) {
+        unsafe { write_volatile(self.value.get().cast::<T>(), value) };
+        cortex_m::asm::dsb();
+    }
+
+    /// Reads the value and leaves `T::default()` behind
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        let value = self.read();
+        self.write(T::default());
+        value
+    }
+}
@@ -0,0 +1,75 @@
+//! Reset cause handed from the bootloader to the running app
+//!
+//! The bootloader is the only image that sees the raw `RCC_CSR` flags (it
+//! clears them after reading), so it decodes them once and stashes the result
+//! in the second noinit word. Apps read it back at any time.
+
+use crate::NoinitCell;
+use core::fmt;
+
+/// Noinit word holding the stashed cause, right after the boot magic
+pub const RESET_CAUSE_ADDR: u32 = crate::MAGIC_ADDR + 4;
+
+// Upper half marks the word as written by the bootloader, so stale RAM
+// contents after a power-on are reported as `Unknown`
+const TAG: u32 = 0x5243_0000; // "RC"
+const TAG_MASK: u32 = 0xFFFF_0000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResetCause {
+    PowerOn,
+    Watchdog,
+    Software,
+    Pin,
+    Unknown,
+}
+
+impl ResetCause {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "power-on",
+            ResetCause::Watchdog => "watchdog",
+            ResetCause::Software => "software",
+            ResetCause::Pin => "pin",
+            ResetCause::Unknown => "unknown",
+        }
+    }
+
+    const fn encode(self) -> u32 {
+        TAG | self as u32
+    }
+
+    const fn decode(word: u32) -> Self {
+        if word & TAG_MASK != TAG {
+            return ResetCause::Unknown;
+        }
+        match word & !TAG_MASK {
+            0 => ResetCause::PowerOn,
+            1 => ResetCause::Watchdog,
+            2 => ResetCause::Software,
+            3 => ResetCause::Pin,
+            _ => ResetCause::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for ResetCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Records the cause of the current boot, called by the bootloader
+pub fn stash_reset_cause(cause: ResetCause) {
+    stash().write(cause.encode());
+}
+
+/// Cause of the last reset, as stashed by the bootloader
+pub fn stashed_reset_cause() -> ResetCause {
+    ResetCause::decode(stash().read())
+}
+
+fn stash() -> &'static NoinitCell<u32> {
+    // RESET_CAUSE_ADDR is reserved by every image's linker script
+    unsafe { NoinitCell::at(RESET_CAUSE_ADDR) }
+}
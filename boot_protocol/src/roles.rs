@@ -0,0 +1,76 @@
+//! Which physical slot plays which app's role
+//!
+//! Boot requests and the apps' own idea of where they run speak in roles:
+//! [`BootTarget::App1`] is the role booted by default and recovered to,
+//! `App2` the other one. Normally each role is the slot of the same name.
+//! With the roles swapped the App2 slot takes over App1's role (and the other
+//! way round) without an image being moved, e.g. to make a freshly installed
+//! image the default and keep the old one around to roll back to.
+//!
+//! The mapping is a bit in the bootloader's settings record in `RTC_BKP5R`
+//! (bootloader/src/persist.rs), which the apps read too. The bootloader backs
+//! the register with a log in flash and refills it before any app runs. It
+//! changes only through the bootloader's `swap` command, so it is fixed while
+//! an app runs.
+
+use crate::BootTarget;
+use core::ptr::read_volatile;
+
+/// `RTC_BKP5R`, holding the bootloader's settings record
+pub const SETTINGS_ADDR: u32 = 0x4000_2864;
+
+/// Upper half of a valid settings record, anything else reads as all unset
+pub const SETTINGS_TAG: u32 = 0xD5F7_0000;
+
+/// Settings record bit set while the roles are swapped
+pub const SETTINGS_ROLES_SWAPPED: u32 = 1 << 4;
+
+/// The role to slot mapping, see the module docs
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct SlotRoles {
+    swapped: bool,
+}
+
+impl SlotRoles {
+    /// Every role in the slot of the same name
+    pub const IDENTITY: Self = Self { swapped: false };
+
+    pub const fn new(swapped: bool) -> Self {
+        Self { swapped }
+    }
+
+    /// The mapping in effect, as persisted by the bootloader
+    ///
+    /// Backup registers read fine without `enable_backup_access`, which only
+    /// unlocks writes.
+    pub fn load() -> Self {
+        let record = unsafe { read_volatile(SETTINGS_ADDR as *const u32) };
+        let valid = record & 0xFFFF_0000 == SETTINGS_TAG;
+        Self::new(valid && record & SETTINGS_ROLES_SWAPPED != 0)
+    }
+
+    pub const fn is_swapped(self) -> bool {
+        self.swapped
+    }
+
+    /// The same mapping the other way round
+    pub const fn swapped(self) -> Self {
+        Self::new(!self.swapped)
+    }
+
+    /// The slot playing `role`
+    pub const fn slot(self, role: BootTarget) -> BootTarget {
+        match (self.swapped, role) {
+            (false, target) => target,
+            (true, BootTarget::App1) => BootTarget::App2,
+            (true, BootTarget::App2) => BootTarget::App1,
+        }
+    }
+
+    /// The role played by `slot`
+    ///
+    /// Two slots, so a swap is its own inverse.
+    pub const fn role(self, slot: BootTarget) -> BootTarget {
+        self.slot(slot)
+    }
+}
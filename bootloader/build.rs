@@ -1,3 +1,6 @@
+#[path = "../build-support/layout.rs"]
+mod layout;
+
 use std::env;
 use std::fs;
 use std::path::PathBuf;
@@ -5,11 +8,49 @@ use std::path::PathBuf;
 fn main() {
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
 
-    // Copy both device.x and memory.x to OUT_DIR
+    // memory.x: the bootloader's sector below the settings log, RAM and the
+    // noinit words from layout.toml, then sections.x. device.x is copied as
+    // is
+    let layout = layout::Layout::load();
+    let noinit = layout::Region {
+        origin: layout.magic_addr(),
+        size: layout.noinit,
+    };
+    layout.write_memory_x(layout.bootloader_code(), &[("NOINIT_RAM", noinit)]);
     fs::copy("device.x", out.join("device.x")).unwrap();
-    fs::copy("memory.x", out.join("memory.x")).unwrap();
 
-    println!("cargo:rustc-link-search={}", out.display());
-    println!("cargo:rerun-if-changed=memory.x");
+    // secure-boot bakes in the raw 32-byte Ed25519 public key
+    if env::var_os("CARGO_FEATURE_SECURE_BOOT").is_some() {
+        let key = env::var("BOOT_PUBLIC_KEY").unwrap_or_else(|_| "keys/boot_public_key.bin".into());
+        let bytes = fs::read(&key).unwrap_or_else(|e| {
+            panic!("secure-boot needs a public key at {key} ({e}), set BOOT_PUBLIC_KEY")
+        });
+        assert_eq!(
+            bytes.len(),
+            32,
+            "{key} must hold a raw 32-byte Ed25519 public key"
+        );
+        fs::write(out.join("boot_public_key.bin"), bytes).unwrap();
+        println!("cargo:rerun-if-changed={key}");
+        println!("cargo:rerun-if-env-changed=BOOT_PUBLIC_KEY");
+    }
+
+    // defmt's section layout, and its level filter: the most verbose log-*
+    // feature wins, an explicit DEFMT_LOG in the environment wins over all
+    if env::var_os("CARGO_FEATURE_DEFMT").is_some() {
+        println!("cargo:rustc-link-arg=-Tdefmt.x");
+
+        let level = ["trace", "debug", "info", "warn", "error"]
+            .into_iter()
+            .find(|level| {
+                env::var_os(format!("CARGO_FEATURE_LOG_{}", level.to_uppercase())).is_some()
+            })
+            .unwrap_or("info");
+        if env::var_os("DEFMT_LOG").is_none() {
+            println!("cargo:rustc-env=DEFMT_LOG={level}");
+        }
+        println!("cargo:rerun-if-env-changed=DEFMT_LOG");
+    }
+
     println!("cargo:rerun-if-changed=device.x");
 }
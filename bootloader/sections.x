@@ -0,0 +1,64 @@
+/* Linked after the MEMORY block build.rs generates from layout.toml:
+ * FLASH is the bootloader's sector up to the settings log at its top
+ * (boot_protocol::SETTINGS_LOG_ADDR), RAM stops below the reserved words at
+ * its top, which start with NOINIT_RAM.
+ *
+ * NOINIT_RAM: Special RAM section that survives soft resets
+ * This 8-byte region at the end of RAM is used to store the "magic value"
+ * that tells the bootloader which application to boot after a reset
+ * (0x2001FFF8), followed by the decoded reset cause (0x2001FFFC).
+ * On STM32F4, SRAM is NOT cleared by software resets (SYSRESETREQ),
+ * only by power-on reset or brownout reset.
+ *
+ * Below NOINIT_RAM come the app heartbeat (boot_protocol::HEARTBEAT_ADDR,
+ * 0x2001FFF0) and the apps' 256-byte panic ring (PANIC_LOG_ADDR,
+ * 0x2001FEF0). The bootloader keeps its stack off both, so they survive a
+ * pass through it. */
+
+SECTIONS
+{
+  /* .noinit section: Uninitialized data that persists across soft resets
+   * 
+   * The (NOLOAD) attribute tells the linker not to initialize this section,
+   * so its contents remain intact during software-triggered resets.
+   * 
+   * This is critical for the bootloader mechanism:
+   * 1. An app writes a magic value (0xDEADBEEF or 0xCAFEBABE) to this section
+   * 2. The app triggers a software reset via SCB->AIRCR SYSRESETREQ bit
+   * 3. The bootloader runs after reset and reads the magic value
+   * 4. Based on the magic value, bootloader jumps to the correct app
+   * 5. Bootloader clears the magic to prevent boot loops
+   * 
+   * Without .noinit, the runtime would zero this memory during startup,
+   * losing the magic value before the bootloader could read it. */
+  .noinit (NOLOAD) : ALIGN(4)
+  {
+    *(.noinit .noinit.*);
+    . = ALIGN(4);
+  } > NOINIT_RAM
+}
+
+/* Bootloader info block (boot_protocol::BootloaderInfo) at a fixed address
+ * just past the vector table (0x198 bytes on the F411), so host tools can
+ * read it at 0x08000200. It gets 0x100 bytes, .text starts after them. */
+SECTIONS
+{
+  .bootloader_info ORIGIN(FLASH) + 0x200 :
+  {
+    KEEP(*(.bootloader_info));
+  } > FLASH
+} INSERT AFTER .vector_table;
+
+_stext = ORIGIN(FLASH) + 0x200 + 0x100;
+
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
+
+/* What clear-ram may zero before a jump (src/ram.rs): the bootloader's RAM,
+ * which stops below the reserved words */
+_clearable_ram_start = ORIGIN(RAM);
+_clearable_ram_end = ORIGIN(RAM) + LENGTH(RAM);
+
+/* The stack grows down from _stack_start; a frame overrunning its top goes
+ * up into the guard gap from layout.toml, never into the reserved words */
+ASSERT(_stack_start + _stack_guard_size <= _reserved_ram_start,
+       "the stack would start on the reserved words, check layout.toml");
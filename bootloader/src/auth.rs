@@ -0,0 +1,227 @@
+//! Challenge-response sessions guarding flash writes in update mode
+//! (`secure-boot` feature)
+//!
+//! With secure boot a flashed image can't run unless it is signed, but anyone
+//! on the UART could still erase a slot, or replay a captured update session.
+//! Update mode therefore only takes commands that write flash once the host
+//! has answered a fresh challenge: `auth` prints [`CHALLENGE_LEN`] random
+//! bytes, the host signs them with the image signing key and sends the
+//! signature back with `auth <hex>`. The session then lasts until update mode
+//! is left. A challenge is used up by the first answer to it, right or wrong.
+//!
+//! Responses are Ed25519ph signatures over the SHA-512 of the challenge, with
+//! [`CONTEXT`] as the context string. The context keeps them apart from image
+//! signatures (which have none), so neither can stand in for the other.
+//!
+//! The challenge comes from the RNG peripheral where the part has one (the
+//! F407; its 48 MHz clock is taken from the otherwise unused PLL, the core
+//! stays on the HSI). The F411 has none, and the RNG may also fail to come up;
+//! then the bytes are a SHA-512 over the cycle counter, which has been running
+//! since update mode was entered and so reflects when the operator typed
+//! `auth`, and the device ID. That is far weaker, and is warned about.
+
+use crate::secure::PUBLIC_KEY;
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::peripheral::DWT;
+use salty::{PublicKey, Sha512, Signature};
+
+/// Random bytes the host signs
+pub const CHALLENGE_LEN: usize = 32;
+
+/// Length of a response, an Ed25519 signature
+pub const RESPONSE_LEN: usize = 64;
+
+/// Ed25519ph context string of session responses
+pub const CONTEXT: &[u8] = b"multiapp-bootloader update session";
+
+/// 96-bit unique device ID
+const UID_ADDR: u32 = 0x1FFF_7A10;
+
+/// Sessions begun since update mode was entered, stirred into fallback
+/// challenges so two of them never come out the same
+static SESSIONS: AtomicU32 = AtomicU32::new(0);
+
+/// Where a challenge's bytes came from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Entropy {
+    Rng,
+    /// No usable RNG, seeded from the cycle counter
+    CycleCounter,
+}
+
+/// A challenge awaiting its response
+///
+/// Neither `Copy` nor `Clone`: [`verify_response`] takes it, so each one can
+/// be answered once.
+pub struct Challenge {
+    bytes: [u8; CHALLENGE_LEN],
+    source: Entropy,
+}
+
+impl Challenge {
+    pub fn bytes(&self) -> &[u8; CHALLENGE_LEN] {
+        &self.bytes
+    }
+
+    pub fn source(&self) -> Entropy {
+        self.source
+    }
+}
+
+/// Authentication state of an update mode visit
+#[derive(Default)]
+pub struct Session {
+    pending: Option<Challenge>,
+    authed: bool,
+}
+
+impl Session {
+    /// Whether a challenge has been answered, so flash may be written
+    pub fn is_authed(&self) -> bool {
+        self.authed
+    }
+
+    /// Issues a new challenge, replacing any unanswered one
+    pub fn challenge(&mut self) -> &Challenge {
+        self.pending.insert(begin_authed_session())
+    }
+
+    /// Checks `response` against the pending challenge, using it up
+    ///
+    /// False without a pending challenge. A wrong answer doesn't end a session
+    /// that is already authenticated.
+    pub fn respond(&mut self, response: &[u8; RESPONSE_LEN]) -> bool {
+        let ok = self
+            .pending
+            .take()
+            .is_some_and(|challenge| verify_response(challenge, response));
+        self.authed |= ok;
+        ok
+    }
+}
+
+/// Starts the cycle counter the fallback seeds from and, where there is one,
+/// the RNG. Called once on entering update mode.
+pub fn init() {
+    crate::enable_cycle_counter();
+
+    #[cfg(feature = "device-f407")]
+    rng::enable();
+}
+
+/// A fresh challenge for the host to sign
+pub fn begin_authed_session() -> Challenge {
+    let mut bytes = [0; CHALLENGE_LEN];
+    let source = if fill_from_rng(&mut bytes) {
+        Entropy::Rng
+    } else {
+        warn!("no RNG, challenge seeded from the cycle counter");
+        fill_from_cycle_counter(&mut bytes);
+        Entropy::CycleCounter
+    };
+    Challenge { bytes, source }
+}
+
+/// Whether `response` is a signature of `challenge` by the image signing key
+pub fn verify_response(challenge: Challenge, response: &[u8; RESPONSE_LEN]) -> bool {
+    let Ok(key) = PublicKey::try_from(PUBLIC_KEY) else {
+        return false;
+    };
+    let mut hash = Sha512::new();
+    hash.update(&challenge.bytes);
+    let digest = hash.finalize();
+
+    key.verify_prehashed(&digest, &Signature::from(response), Some(CONTEXT))
+        .is_ok()
+}
+
+#[cfg(feature = "device-f407")]
+fn fill_from_rng(bytes: &mut [u8; CHALLENGE_LEN]) -> bool {
+    for chunk in bytes.chunks_exact_mut(4) {
+        let Some(word) = rng::next_word() else {
+            return false;
+        };
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    true
+}
+
+#[cfg(not(feature = "device-f407"))]
+fn fill_from_rng(_bytes: &mut [u8; CHALLENGE_LEN]) -> bool {
+    false
+}
+
+fn fill_from_cycle_counter(bytes: &mut [u8; CHALLENGE_LEN]) {
+    let uid = unsafe { core::slice::from_raw_parts(UID_ADDR as *const u8, 12) };
+    let session = SESSIONS.fetch_add(1, Ordering::Relaxed);
+
+    let mut hash = Sha512::new();
+    hash.update(uid);
+    hash.update(&session.to_le_bytes());
+    hash.update(&DWT::cycle_count().to_le_bytes());
+    let digest = hash.finalize();
+    bytes.copy_from_slice(&digest[..CHALLENGE_LEN]);
+}
+
+/// The F407's RNG, by raw address: the PAC in use is the F411's, which has
+/// no RNG
+#[cfg(feature = "device-f407")]
+mod rng {
+    use core::ptr::{read_volatile, write_volatile};
+    use stm32f4::stm32f411 as pac;
+
+    const RNG_CR: *mut u32 = 0x5006_0800 as *mut u32;
+    const RNG_SR: *const u32 = 0x5006_0804 as *const u32;
+    const RNG_DR: *const u32 = 0x5006_0808 as *const u32;
+
+    const CR_RNGEN: u32 = 1 << 2;
+    const SR_DRDY: u32 = 1 << 0;
+    const SR_CECS: u32 = 1 << 1;
+    const SR_SECS: u32 = 1 << 2;
+
+    const AHB2ENR_RNGEN: u32 = 1 << 6;
+
+    // PLL from the HSI: 16 MHz / M 16 * N 192 / Q 4 = 48 MHz for the RNG.
+    // The P output is never selected as the system clock
+    const PLLCFGR_48MHZ_Q: u32 = 0x2000_0000 | (4 << 24) | (192 << 6) | 16;
+    const CR_PLLON: u32 = 1 << 24;
+    const CR_PLLRDY: u32 = 1 << 25;
+
+    /// Polls before giving up on PLL lock or a random word (a word takes 40
+    /// RNG clocks, the lock well under a millisecond)
+    const SPIN_LIMIT: u32 = 100_000;
+
+    pub fn enable() {
+        let rcc = unsafe { pac::RCC::steal() };
+        if rcc.cr().read().bits() & CR_PLLON == 0 {
+            rcc.pllcfgr().write(|w| unsafe { w.bits(PLLCFGR_48MHZ_Q) });
+            rcc.cr()
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR_PLLON) });
+        }
+        rcc.ahb2enr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | AHB2ENR_RNGEN) });
+        unsafe { write_volatile(RNG_CR, CR_RNGEN) };
+    }
+
+    fn pll_locked() -> bool {
+        let rcc = unsafe { pac::RCC::steal() };
+        (0..SPIN_LIMIT).any(|_| rcc.cr().read().bits() & CR_PLLRDY != 0)
+    }
+
+    /// A random word, `None` if the RNG doesn't deliver or flags an error
+    pub fn next_word() -> Option<u32> {
+        if !pll_locked() {
+            return None;
+        }
+        for _ in 0..SPIN_LIMIT {
+            let sr = unsafe { read_volatile(RNG_SR) };
+            if sr & (SR_CECS | SR_SECS) != 0 {
+                return None;
+            }
+            if sr & SR_DRDY != 0 {
+                return Some(unsafe { read_volatile(RNG_DR) });
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,118 @@
+//! Brown-out reset threshold, kept in the option bytes
+//!
+//! The BOR holds the chip in reset while VDD is below the programmed level.
+//! Out of the factory it is off (only the ~1.7 V power-down reset applies),
+//! so a sagging supply can leave the core running, and programming flash,
+//! well below the 2.7 V that x32 programming (`flash::CR_PSIZE_X32`) needs.
+//!
+//! Option bytes are only rewritten when the level differs: the first boot
+//! after flashing a fresh chip programs them and resets once, every later
+//! boot just reads them back.
+
+use crate::flash;
+use core::fmt;
+use stm32f4::stm32f411 as pac;
+
+const OPTKEY1: u32 = 0x0819_2A3B;
+const OPTKEY2: u32 = 0x4C5D_6E7F;
+
+// FLASH_OPTCR bits
+const OPTCR_OPTLOCK: u32 = 1 << 0;
+const OPTCR_OPTSTRT: u32 = 1 << 1;
+const OPTCR_BOR_SHIFT: u32 = 2;
+const OPTCR_BOR_MASK: u32 = 0b11 << OPTCR_BOR_SHIFT;
+
+/// BOR_LEV option byte values, thresholds for a rising supply
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BorLevel {
+    /// About 2.70 V
+    Level3 = 0b00,
+    /// About 2.40 V
+    Level2 = 0b01,
+    /// About 2.10 V
+    Level1 = 0b10,
+    /// No brown-out reset, only power-on/power-down
+    Off = 0b11,
+}
+
+impl BorLevel {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0b00 => BorLevel::Level3,
+            0b01 => BorLevel::Level2,
+            0b10 => BorLevel::Level1,
+            _ => BorLevel::Off,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BorLevel::Level3 => "level 3 (2.7 V)",
+            BorLevel::Level2 => "level 2 (2.4 V)",
+            BorLevel::Level1 => "level 1 (2.1 V)",
+            BorLevel::Off => "off",
+        }
+    }
+}
+
+impl fmt::Display for BorLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn regs() -> pac::FLASH {
+    unsafe { pac::FLASH::steal() }
+}
+
+/// The level the option bytes currently select
+pub fn current_level() -> BorLevel {
+    BorLevel::from_bits(regs().optcr().read().bits() >> OPTCR_BOR_SHIFT)
+}
+
+/// Programs `level` into the option bytes unless it is already set, then
+/// resets so the chip comes up with it
+///
+/// Returns only when nothing had to change, or when the write didn't take
+/// (e.g. option bytes frozen by RDP level 2): booting on with the old level
+/// beats resetting into the same attempt forever.
+pub fn ensure_bor_level(level: BorLevel) {
+    let current = current_level();
+    if current == level {
+        info!("BOR {=str}", current.as_str());
+        return;
+    }
+
+    warn!(
+        "BOR {=str}, programming {=str}",
+        current.as_str(),
+        level.as_str()
+    );
+    // Programming the option bytes takes about as long as a sector erase
+    let flash = regs();
+    let result = flash::wait_done(&flash, flash::ERASE_TIMEOUT_CYCLES).and_then(|()| {
+        if flash.optcr().read().bits() & OPTCR_OPTLOCK != 0 {
+            flash.optkeyr().write(|w| unsafe { w.bits(OPTKEY1) });
+            flash.optkeyr().write(|w| unsafe { w.bits(OPTKEY2) });
+        }
+        flash.optcr().modify(|r, w| unsafe {
+            w.bits((r.bits() & !OPTCR_BOR_MASK) | ((level as u32) << OPTCR_BOR_SHIFT))
+        });
+        flash
+            .optcr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | OPTCR_OPTSTRT) });
+        flash::wait_done(&flash, flash::ERASE_TIMEOUT_CYCLES)
+    });
+    flash
+        .optcr()
+        .modify(|r, w| unsafe { w.bits(r.bits() | OPTCR_OPTLOCK) });
+
+    if result.is_err() || current_level() != level {
+        error!(
+            "BOR option byte write failed, keeping {=str}",
+            current.as_str()
+        );
+        return;
+    }
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -0,0 +1,77 @@
+//! Boot console: the bootloader's defmt log on USART2 (`boot-console` feature)
+//!
+//! Replaces RTT as defmt's transport, so boot decisions can be read with only
+//! a serial adapter on PA2 (115200 8N1, the apps' pins). The frames are
+//! defmt's binary encoding; decode them on the host with the bootloader ELF,
+//! e.g. `defmt-print -e bootloader serial --path /dev/ttyUSB0`.
+//!
+//! Bringing the UART up is a few register writes, it doesn't hold up the
+//! boot. Every log line then costs roughly a millisecond on the wire, since
+//! the transmitter is polled. With `silent-boot` the UART is never set up and
+//! the frames are dropped.
+
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+use stm32f4::stm32f411 as pac;
+
+// Set once the UART is up, frames are dropped until then
+static READY: AtomicBool = AtomicBool::new(false);
+
+// Logger state, logging runs with interrupts masked
+static TAKEN: AtomicBool = AtomicBool::new(false);
+static WAS_ENABLED: AtomicBool = AtomicBool::new(false);
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+/// Brings USART2 up for the log, unless built with `silent-boot`
+pub fn init_boot_console() {
+    if cfg!(feature = "silent-boot") {
+        return;
+    }
+    crate::serial::BootSerial::init();
+    READY.store(true, Ordering::Relaxed);
+}
+
+fn write(bytes: &[u8]) {
+    if !READY.load(Ordering::Relaxed) {
+        return;
+    }
+    let usart = unsafe { &*pac::USART2::ptr() };
+    for &byte in bytes {
+        while usart.sr().read().txe().bit_is_clear() {}
+        usart.dr().write(|w| unsafe { w.bits(byte as u32) });
+    }
+}
+
+#[defmt::global_logger]
+struct BootConsole;
+
+unsafe impl defmt::Logger for BootConsole {
+    fn acquire() {
+        let primask = cortex_m::register::primask::read();
+        cortex_m::interrupt::disable();
+        if TAKEN.swap(true, Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly");
+        }
+        WAS_ENABLED.store(primask.is_active(), Ordering::Relaxed);
+        unsafe { (*addr_of_mut!(ENCODER)).start_frame(write) };
+    }
+
+    unsafe fn flush() {
+        let usart = &*pac::USART2::ptr();
+        if READY.load(Ordering::Relaxed) {
+            while usart.sr().read().tc().bit_is_clear() {}
+        }
+    }
+
+    unsafe fn release() {
+        (*addr_of_mut!(ENCODER)).end_frame(write);
+        TAKEN.store(false, Ordering::Relaxed);
+        if WAS_ENABLED.load(Ordering::Relaxed) {
+            cortex_m::interrupt::enable();
+        }
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*addr_of_mut!(ENCODER)).write(bytes, write);
+    }
+}
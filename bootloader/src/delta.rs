@@ -0,0 +1,209 @@
+//! Delta updates: rebuilding an image from another slot plus a patch
+//!
+//! A patch describes the new image as a sequence of copies out of a base
+//! slot and literal inserts, so a small change only costs the bytes that
+//! actually differ. All values are little-endian:
+//!
+//! ```text
+//! header:  magic u32 ("DLTA") | image_len u32 | image_crc u32
+//! ops:     0x01 COPY   base_offset u32 | len u32
+//!          0x02 INSERT len u32 | data[len]
+//!          0x00 END
+//! ```
+//!
+//! `image_len` must be word-aligned and fit the target slot (see
+//! `flash::validate_image_len`); it is checked before any op is read.
+//! `image_crc` is the CRC-32 of the complete rebuilt image (`image_len`
+//! bytes from the slot base). The first 8 bytes (initial SP and reset
+//! vector) are only written once that CRC has checked out, so an image that
+//! fails verification never passes `slot::is_valid_app` and won't be booted.
+
+use crate::flash::{slot_capacity, validate_image_len, write_slot, FlashError};
+use boot_protocol::BootTarget;
+use bootloader_core::Crc32;
+
+/// "DLTA" in little-endian ASCII
+pub const PATCH_MAGIC: u32 = 0x4154_4C44;
+
+const OP_END: u8 = 0x00;
+const OP_COPY: u8 = 0x01;
+const OP_INSERT: u8 = 0x02;
+
+// Bytes held back until the image is verified: initial SP + reset vector
+pub const HEAD_LEN: usize = 8;
+
+// Program granularity, a multiple of the 4-byte write unit
+const BLOCK: usize = 256;
+
+/// Where the patch bytes come from
+pub trait PatchStream {
+    /// Fills `buf` completely, or fails
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), FlashError>;
+}
+
+fn read_u32(stream: &mut impl PatchStream) -> Result<u32, FlashError> {
+    let mut bytes = [0; 4];
+    stream.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Buffers an incoming image and programs it block by block
+///
+/// The first `HEAD_LEN` bytes (initial SP and reset vector) are held back
+/// until [`commit`](Self::commit), so a slot whose transfer is cut short
+/// never looks bootable. Also used by the YMODEM receiver.
+pub struct SlotWriter {
+    target: BootTarget,
+    limit: u32,
+    /// Slot offset of `buf[0]`
+    offset: u32,
+    buf: [u8; BLOCK],
+    len: usize,
+    head: [u8; HEAD_LEN],
+}
+
+impl SlotWriter {
+    /// Writer for an image of `limit` bytes into the (erased) `target`
+    ///
+    /// The limit is held to the slot's capacity whatever the caller asks for,
+    /// so pushes past it fail before anything reaches flash.
+    pub fn new(target: BootTarget, limit: u32) -> Self {
+        Self {
+            target,
+            limit: limit.min(slot_capacity(target)),
+            offset: 0,
+            buf: [0; BLOCK],
+            len: 0,
+            head: [0xFF; HEAD_LEN],
+        }
+    }
+
+    pub fn push(&mut self, mut bytes: &[u8]) -> Result<(), FlashError> {
+        if self.offset + (self.len + bytes.len()) as u32 > self.limit {
+            return Err(FlashError::OutOfBounds);
+        }
+        while !bytes.is_empty() {
+            let n = bytes.len().min(BLOCK - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+            if self.len == BLOCK {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), FlashError> {
+        let mut data = &self.buf[..self.len];
+        let mut offset = self.offset;
+        if offset == 0 {
+            self.head.copy_from_slice(&data[..HEAD_LEN]);
+            data = &data[HEAD_LEN..];
+            offset = HEAD_LEN as u32;
+        }
+        write_slot(self.target, offset, data)?;
+
+        self.offset += self.len as u32;
+        self.len = 0;
+        Ok(())
+    }
+
+    /// Bytes pushed so far
+    pub fn written(&self) -> u32 {
+        self.offset + self.len as u32
+    }
+
+    /// Programs the held-back vector table, once everything else is flushed
+    ///
+    /// With it in place the slot becomes bootable.
+    pub fn commit(&self) -> Result<(), FlashError> {
+        write_slot(self.target, 0, &self.head)
+    }
+}
+
+/// Rebuilds `target_slot` from `base_slot` and a patch read from `stream`
+///
+/// `target_slot` must have been erased beforehand, and has to differ from
+/// `base_slot`, whose contents are read while the target is programmed.
+/// Returns the length of the rebuilt image once its CRC matched the patch
+/// header and the vector table has been written.
+pub fn apply_patch(
+    base_slot: BootTarget,
+    target_slot: BootTarget,
+    stream: &mut impl PatchStream,
+) -> Result<u32, FlashError> {
+    if base_slot == target_slot {
+        return Err(FlashError::BadPatch);
+    }
+
+    if read_u32(stream)? != PATCH_MAGIC {
+        return Err(FlashError::BadPatch);
+    }
+    let image_len = read_u32(stream)?;
+    let image_crc = read_u32(stream)?;
+    validate_image_len(target_slot, image_len)?;
+    if (image_len as usize) < HEAD_LEN {
+        return Err(FlashError::BadPatch);
+    }
+
+    let mut writer = SlotWriter::new(target_slot, image_len);
+
+    loop {
+        let mut op = [0];
+        stream.read_exact(&mut op)?;
+        match op[0] {
+            OP_END => break,
+            OP_COPY => {
+                let from = read_u32(stream)?;
+                let len = read_u32(stream)?;
+                if from
+                    .checked_add(len)
+                    .is_none_or(|end| end > base_slot.size())
+                {
+                    return Err(FlashError::OutOfBounds);
+                }
+                let src = unsafe {
+                    core::slice::from_raw_parts(
+                        (base_slot.addr() + from) as *const u8,
+                        len as usize,
+                    )
+                };
+                writer.push(src)?;
+            }
+            OP_INSERT => {
+                let mut left = read_u32(stream)? as usize;
+                let mut chunk = [0; 64];
+                while left > 0 {
+                    let n = left.min(chunk.len());
+                    stream.read_exact(&mut chunk[..n])?;
+                    writer.push(&chunk[..n])?;
+                    left -= n;
+                }
+            }
+            _ => return Err(FlashError::BadPatch),
+        }
+    }
+
+    if writer.written() != image_len {
+        return Err(FlashError::BadPatch);
+    }
+    writer.flush()?;
+
+    // Everything but the held-back head is in flash now
+    let rest = unsafe {
+        core::slice::from_raw_parts(
+            (target_slot.addr() + HEAD_LEN as u32) as *const u8,
+            image_len as usize - HEAD_LEN,
+        )
+    };
+    let mut crc = Crc32::new();
+    crc.update(&writer.head);
+    crc.update(rest);
+    if crc.finish() != image_crc {
+        return Err(FlashError::VerifyFailed);
+    }
+
+    writer.commit()?;
+    Ok(image_len)
+}
@@ -0,0 +1,320 @@
+//! Erasing and programming the application slots, and the settings log
+//!
+//! Sector boundaries come from the part's [`layout::LAYOUT`]. On the
+//! STM32F411xE (512KB) the slots sit on them like this:
+//!
+//! | Sector | Address      | Size  | Owner                           |
+//! |--------|--------------|-------|---------------------------------|
+//! | 0      | 0x0800_0000  | 16KB  | bootloader                      |
+//! | 1-3    | 0x0800_4000  | 16KB  | App1                            |
+//! | 4      | 0x0801_0000  | 64KB  | App1                            |
+//! | 5      | 0x0802_0000  | 128KB | App2 (first 16KB unused)        |
+//! | 6-7    | 0x0804_0000  | 128KB | App2                            |
+//!
+//! Sectors are the erase unit, and App2's base (0x0802_4000) falls in the
+//! middle of sector 5. Sector 5 is treated as App2's, so erasing App2 also
+//! wipes 0x0802_0000..0x0802_4000. `layout.toml` therefore ends App1 at
+//! 0x0802_0000 (112KB), so the linker refuses an App1 that would reach into
+//! sector 5. Every write path also holds a slot to its [`slot_capacity`],
+//! which ends it at such a shared sector for any layout.
+//!
+//! The F407 starts with the same sectors and adds 128KB ones (8-11) above
+//! 0x0808_0000, which the slots don't reach.
+//!
+//! Erasing or programming flash the CPU is executing from would crash it
+//! halfway, so [`erase_slot`] and [`write_slot`] first refuse with
+//! [`FlashError::RunningSlot`] when this code runs from the slot (for an
+//! erase: from any sector it wipes).
+//! Update mode runs from the bootloader's sector and never trips this; it
+//! guards these functions being called from an app. Such a host has to switch
+//! to the other app first.
+//!
+//! The settings log at the top of the bootloader's own sector (see
+//! `crate::persist`) is only ever programmed a word at a time with
+//! [`write_settings_word`], never erased. Programming stalls instruction
+//! fetches from the sector until the word is written, which the bootloader
+//! running there rides out.
+//!
+//! Not implemented, the alternative would be staging: receive the image into
+//! a scratch area (the other slot, or spare sectors past the slots on the
+//! F407), mark it pending in a backup register, and have the bootloader copy
+//! it over the target on the next boot before anything runs from there. That
+//! costs a full copy per update and needs the copy to resume after a power
+//! loss, which is why updates go through update mode instead.
+
+mod layout;
+
+use boot_protocol::{BootTarget, SETTINGS_LOG_ADDR, SETTINGS_LOG_SIZE};
+use bootloader_core::{FlashFault, FlashStatus};
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::ptr::write_volatile;
+use cortex_m::peripheral::DWT;
+use stm32f4::stm32f411 as pac;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+// FLASH_CR bits
+const CR_PG: u32 = 1 << 0;
+const CR_SER: u32 = 1 << 1;
+const CR_SNB_SHIFT: u32 = 3;
+const CR_PSIZE_X32: u32 = 0b10 << 8;
+const CR_STRT: u32 = 1 << 16;
+const CR_LOCK: u32 = 1 << 31;
+
+// Longest an operation may keep BSY set before it is given up on, about
+// twice the datasheet maximum (2 s for a 128KB sector, 100 us per word)
+pub const ERASE_TIMEOUT_CYCLES: u32 = crate::CORE_HZ * 4;
+const PROGRAM_TIMEOUT_CYCLES: u32 = crate::CORE_HZ / 5_000;
+
+// FLASH_OPTCR bits
+const OPTCR_RDP_SHIFT: u32 = 8;
+const OPTCR_NWRP_SHIFT: u32 = 16;
+
+// RDP option byte value for level 0 (no read protection)
+const RDP_LEVEL_0: u32 = 0xAA;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlashError {
+    /// A sector to be written is write-protected in the option bytes
+    Locked,
+    /// Write falls outside the slot or isn't word aligned
+    OutOfBounds,
+    /// The controller flagged an error, or stayed busy too long
+    Controller(FlashFault),
+    /// An update's data stream is malformed
+    BadPatch,
+    /// The programmed image doesn't match its expected CRC
+    VerifyFailed,
+    /// Flash is read-protected (RDP level 1 or 2), contents won't be exported
+    ReadProtected,
+    /// The code asked to do it runs from the slot, see the module docs
+    RunningSlot,
+    /// Every word of the settings log has been written
+    LogFull,
+}
+
+impl From<FlashFault> for FlashError {
+    fn from(fault: FlashFault) -> Self {
+        FlashError::Controller(fault)
+    }
+}
+
+impl fmt::Display for FlashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FlashError::Locked => "sector is write-protected (WRP option bytes)",
+            FlashError::OutOfBounds => "write outside slot or misaligned",
+            FlashError::Controller(fault) => fault.as_str(),
+            FlashError::BadPatch => "malformed patch",
+            FlashError::VerifyFailed => "image CRC mismatch after programming",
+            FlashError::ReadProtected => "flash is read-protected (RDP)",
+            FlashError::RunningSlot => "slot is running, switch to the other app first",
+            FlashError::LogFull => "settings log full, reflash the bootloader to clear it",
+        })
+    }
+}
+
+/// Bytes from the slot's base an image may fill, see the module docs for the
+/// sector 5 caveat
+pub fn slot_capacity(target: BootTarget) -> u32 {
+    layout::LAYOUT.slot_capacity(target)
+}
+
+/// Sectors erased for a slot
+///
+/// Runs from the sector holding the slot's base to the one holding the last
+/// byte of its capacity, so a last sector that also holds the next slot's
+/// base is left to that slot: erasing it from here would wipe the next image.
+fn slot_sectors(target: BootTarget) -> RangeInclusive<u8> {
+    let layout = &layout::LAYOUT;
+    let sector = |addr| layout.sector_of(addr).expect("slot outside flash");
+
+    sector(target.addr())..=sector(target.addr() + slot_capacity(target) - 1)
+}
+
+/// An address in the code doing the flash work, where the CPU executes from
+fn running_addr() -> u32 {
+    running_addr as usize as u32
+}
+
+/// The slot this code runs from, `None` from the bootloader's sector (or RAM)
+pub fn running_slot() -> Option<BootTarget> {
+    BootTarget::containing(running_addr())
+}
+
+/// Whether erasing `target` would wipe the code running it
+fn erase_hits_running_code(target: BootTarget) -> bool {
+    layout::LAYOUT
+        .sector_of(running_addr())
+        .is_some_and(|sector| slot_sectors(target).contains(&sector))
+}
+
+fn regs() -> pac::FLASH {
+    unsafe { pac::FLASH::steal() }
+}
+
+/// Whether `sector` is write-protected
+///
+/// Reads its nWRP bit in `FLASH_OPTCR` (cleared bit = protected sector).
+/// Assumes SPRMOD is 0; with PCROP enabled the same bits mean read protection.
+fn is_sector_write_protected(sector: u8) -> bool {
+    let nwrp = regs().optcr().read().bits() >> OPTCR_NWRP_SHIFT;
+    nwrp & (1 << sector) == 0
+}
+
+/// Whether any sector backing the slot is write-protected
+pub fn is_slot_write_protected(target: BootTarget) -> bool {
+    slot_sectors(target).any(is_sector_write_protected)
+}
+
+/// Whether readout protection is active (RDP option byte other than 0xAA)
+///
+/// Code running from flash can still read it, but the bootloader treats
+/// protected contents as not for export.
+pub fn is_read_protected() -> bool {
+    (regs().optcr().read().bits() >> OPTCR_RDP_SHIFT) & 0xFF != RDP_LEVEL_0
+}
+
+fn unlock(flash: &pac::FLASH) {
+    if flash.cr().read().bits() & CR_LOCK != 0 {
+        flash.keyr().write(|w| unsafe { w.bits(KEY1) });
+        flash.keyr().write(|w| unsafe { w.bits(KEY2) });
+    }
+}
+
+fn lock(flash: &pac::FLASH) {
+    flash.cr().write(|w| unsafe { w.bits(CR_LOCK) });
+}
+
+/// The controller's status register, timed by the DWT cycle counter
+struct Status<'a>(&'a pac::FLASH);
+
+impl FlashStatus for Status<'_> {
+    fn status(&mut self) -> u32 {
+        self.0.sr().read().bits()
+    }
+
+    fn clear(&mut self, flags: u32) {
+        self.0.sr().write(|w| unsafe { w.bits(flags) });
+    }
+
+    fn cycles(&mut self) -> u32 {
+        DWT::cycle_count()
+    }
+}
+
+/// Waits up to `timeout_cycles` for the current operation, then reports and
+/// clears any error flags, see `bootloader_core::wait_idle`
+pub fn wait_done(flash: &pac::FLASH, timeout_cycles: u32) -> Result<(), FlashError> {
+    crate::enable_cycle_counter();
+    let mut status = Status(flash);
+    Ok(bootloader_core::wait_idle(&mut status, timeout_cycles)?)
+}
+
+/// Runs `op` with the controller unlocked, locking it again afterwards
+fn unlocked<T>(op: impl FnOnce(&pac::FLASH) -> Result<T, FlashError>) -> Result<T, FlashError> {
+    let flash = regs();
+    unlock(&flash);
+    let result = op(&flash);
+    lock(&flash);
+    result
+}
+
+/// Checks an update's declared image length before anything is received,
+/// see `bootloader_core::image_len_ok`
+pub fn validate_image_len(target: BootTarget, declared_len: u32) -> Result<(), FlashError> {
+    if !bootloader_core::image_len_ok(&layout::LAYOUT, target, declared_len) {
+        return Err(FlashError::OutOfBounds);
+    }
+    Ok(())
+}
+
+/// Erases every sector of the slot
+pub fn erase_slot(target: BootTarget) -> Result<(), FlashError> {
+    if erase_hits_running_code(target) {
+        return Err(FlashError::RunningSlot);
+    }
+    if is_slot_write_protected(target) {
+        return Err(FlashError::Locked);
+    }
+    // Whatever was verified is going away
+    #[cfg(feature = "verify-cache")]
+    crate::verify_cache::invalidate(target);
+
+    unlocked(|flash| {
+        for sector in slot_sectors(target) {
+            let cr = CR_SER | ((sector as u32) << CR_SNB_SHIFT) | CR_PSIZE_X32;
+            flash.cr().write(|w| unsafe { w.bits(cr) });
+            flash.cr().write(|w| unsafe { w.bits(cr | CR_STRT) });
+            wait_done(flash, ERASE_TIMEOUT_CYCLES)?;
+        }
+        Ok(())
+    })
+}
+
+/// Programs `data` at `offset` from the slot's base, one word at a time
+///
+/// The target range must have been erased first and lie within the slot's
+/// [`slot_capacity`]. `offset` and `data.len()` must be multiples of 4.
+pub fn write_slot(target: BootTarget, offset: u32, data: &[u8]) -> Result<(), FlashError> {
+    if running_slot() == Some(target) {
+        return Err(FlashError::RunningSlot);
+    }
+    if is_slot_write_protected(target) {
+        return Err(FlashError::Locked);
+    }
+
+    let len = data.len() as u32;
+    let aligned = offset.is_multiple_of(4) && len.is_multiple_of(4);
+    if !aligned || offset.saturating_add(len) > slot_capacity(target) {
+        return Err(FlashError::OutOfBounds);
+    }
+
+    unlocked(|flash| {
+        flash
+            .cr()
+            .write(|w| unsafe { w.bits(CR_PG | CR_PSIZE_X32) });
+        let mut addr = target.addr() + offset;
+        for word in data.chunks_exact(4) {
+            let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            program_word(flash, addr, value)?;
+            addr += 4;
+        }
+        Ok(())
+    })
+}
+
+/// Programs one word, with `FLASH_CR` already set up for word programming
+fn program_word(flash: &pac::FLASH, addr: u32, value: u32) -> Result<(), FlashError> {
+    unsafe { write_volatile(addr as *mut u32, value) };
+    wait_done(flash, PROGRAM_TIMEOUT_CYCLES)
+}
+
+/// The settings log as it stands, see `bootloader_core::latest_record`
+pub fn settings_log() -> &'static [u32] {
+    let words = (SETTINGS_LOG_SIZE / 4) as usize;
+    unsafe { core::slice::from_raw_parts(SETTINGS_LOG_ADDR as *const u32, words) }
+}
+
+/// Programs word `index` of the settings log, which has to be erased still
+pub fn write_settings_word(index: usize, value: u32) -> Result<(), FlashError> {
+    if index >= settings_log().len() {
+        return Err(FlashError::LogFull);
+    }
+    let addr = SETTINGS_LOG_ADDR + 4 * index as u32;
+    if layout::LAYOUT
+        .sector_of(addr)
+        .is_none_or(is_sector_write_protected)
+    {
+        return Err(FlashError::Locked);
+    }
+
+    unlocked(|flash| {
+        flash
+            .cr()
+            .write(|w| unsafe { w.bits(CR_PG | CR_PSIZE_X32) });
+        program_word(flash, addr, value)
+    })
+}
@@ -0,0 +1,16 @@
+//! The target part's sector geometry, picked by a `device-*` feature from
+//! the ones in `bootloader_core`
+
+use bootloader_core::FlashLayout;
+
+#[cfg(all(feature = "device-f411", feature = "device-f407"))]
+compile_error!("features `device-f411` and `device-f407` are mutually exclusive");
+
+#[cfg(not(any(feature = "device-f411", feature = "device-f407")))]
+compile_error!("select the target part with `device-f411` or `device-f407`");
+
+#[cfg(feature = "device-f411")]
+pub const LAYOUT: FlashLayout = bootloader_core::F411_LAYOUT;
+
+#[cfg(feature = "device-f407")]
+pub const LAYOUT: FlashLayout = bootloader_core::F407_LAYOUT;
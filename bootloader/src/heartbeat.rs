@@ -0,0 +1,32 @@
+//! Falling back from an app that never reached its main loop
+//!
+//! `launch` arms the heartbeat for the slot it jumps into (see
+//! `boot_protocol::arm_heartbeat`) and the app bumps the counter from its
+//! loop. An app that faults, hangs or is reset by the watchdog during start-up
+//! leaves the counter at zero, and on the way back up its slot is only booted
+//! if the other one can't be.
+
+use boot_protocol::{BootTarget, ResetCause};
+
+/// Whether the app booted last time got as far as ticking the heartbeat
+///
+/// Also true when there is nothing to judge: nothing was armed (the last boot
+/// stayed in the bootloader), or a power-on, after which the noinit words
+/// hold whatever the RAM powered up with.
+pub fn previous_app_ran() -> bool {
+    if boot_protocol::stashed_reset_cause() == ResetCause::PowerOn {
+        return true;
+    }
+    boot_protocol::armed_heartbeat().is_none() || boot_protocol::heartbeat_count() != 0
+}
+
+/// The slot booted last time if its app never ticked, `None` otherwise
+///
+/// Forgets the armed slot, so call it once per boot, before anything else
+/// looks at the request: a boot into update mode or the self-test clears the
+/// suspicion along with it.
+pub fn take_suspect_slot() -> Option<BootTarget> {
+    let suspect = boot_protocol::armed_heartbeat().filter(|_| !previous_app_ran());
+    boot_protocol::disarm_heartbeat();
+    suspect
+}
@@ -0,0 +1,45 @@
+//! This bootloader's [`BootloaderInfo`], at `BOOTLOADER_INFO_ADDR`
+
+use boot_protocol::{
+    pack_version, parse_u8, BootloaderInfo, APP1_SIZE, APP2_SIZE, FEATURE_RTC_BACKUP,
+    FEATURE_SECURE_BOOT, FEATURE_SELF_TEST, FEATURE_UPDATE_MODE, INFO_MAGIC, PROTOCOL_VERSION,
+};
+
+/// `bit` if the feature is built in, else nothing
+const fn if_enabled(enabled: bool, bit: u32) -> u32 {
+    if enabled {
+        bit
+    } else {
+        0
+    }
+}
+
+const FEATURES: u32 = FEATURE_UPDATE_MODE
+    | FEATURE_SELF_TEST
+    | if_enabled(cfg!(feature = "rtc-backup"), FEATURE_RTC_BACKUP)
+    | if_enabled(cfg!(feature = "secure-boot"), FEATURE_SECURE_BOOT);
+
+const MAX_IMAGE_SIZE: u32 = if APP1_SIZE > APP2_SIZE {
+    APP1_SIZE
+} else {
+    APP2_SIZE
+};
+
+// sections.x puts .bootloader_info right after the vector table
+#[link_section = ".bootloader_info"]
+#[used]
+static BOOTLOADER_INFO: BootloaderInfo = BootloaderInfo {
+    magic: INFO_MAGIC,
+    protocol_version: PROTOCOL_VERSION,
+    version: pack_version(
+        parse_u8(env!("CARGO_PKG_VERSION_MAJOR")),
+        parse_u8(env!("CARGO_PKG_VERSION_MINOR")),
+        parse_u8(env!("CARGO_PKG_VERSION_PATCH")),
+    ),
+    max_image_size: MAX_IMAGE_SIZE,
+    features: FEATURES,
+};
+
+pub fn bootloader_info() -> &'static BootloaderInfo {
+    &BOOTLOADER_INFO
+}
@@ -0,0 +1,112 @@
+//! Hand-off from the bootloader to an application image
+//!
+//! The decision of *where* to go lives in `main`; this module only knows *how*
+//! to get there. Keeping the transfer behind the `Jumper` trait lets the QEMU
+//! harness swap in a double that reports the chosen address instead of jumping.
+
+/// Transfers control to the application whose vector table starts at `addr`
+pub trait Jumper {
+    fn jump(&self, addr: u32) -> !;
+}
+
+/// Real hardware jump: relocate VTOR and branch into the app's reset handler
+#[cfg_attr(feature = "qemu", allow(dead_code))]
+pub struct CortexMJumper;
+
+impl Jumper for CortexMJumper {
+    fn jump(&self, addr: u32) -> ! {
+        // The slot addresses come from the fixed memory layout, so they always
+        // point at the start of an application's vector table
+        unsafe { jump_to_app(addr) }
+    }
+}
+
+/// Jumps to an application at the given address
+///
+/// Puts the core into the state it would be in straight out of reset before
+/// handing over, instead of trusting whatever the bootloader left behind:
+///
+/// 1. `CPSID i`: mask interrupts so nothing can fire through a half-switched
+///    vector table, then stop SysTick and disable and unpend every NVIC line
+///    (and a pending PendSV/SysTick), as a reset would have left them.
+/// 2. `CONTROL`: select MSP (`SPSEL = 0`), privileged thread mode (`nPRIV = 0`)
+///    and no active FP context (`FPCA = 0`), followed by an `ISB`.
+/// 3. `VTOR = addr`, the app's vector table, followed by `DSB` + `ISB` so the
+///    write has taken effect before anything can be dispatched through it.
+///    Then `CPSIE i`: the app starts with PRIMASK clear, like out of reset.
+///    Nothing is enabled that could fire before its own setup.
+/// 4. Load the app's initial MSP from `addr[0]` and its reset handler from
+///    `addr[1]`, set MSP and branch to the reset handler.
+///
+/// Step 4 is a single `asm::bootstrap` call rather than `msp::write` followed
+/// by a jump: moving the stack pointer under compiled Rust code is undefined
+/// behaviour (which is why `msp::write` is deprecated), whereas `bootstrap`
+/// sets MSP and branches in one asm block that never touches the stack.
+///
+/// # Safety
+/// This must point to a valid application with proper vector table
+unsafe fn jump_to_app(addr: u32) -> ! {
+    use cortex_m::peripheral::{NVIC, SCB, SYST};
+    use cortex_m::register::control::{self, Fpca, Npriv, Spsel};
+
+    // ICSR: PENDSVCLR, PENDSTCLR
+    const ICSR_CLEAR_PENDING: u32 = (1 << 27) | (1 << 25);
+
+    // 1) No interrupts during the switch, and none left over for the app
+    cortex_m::interrupt::disable();
+    (*SYST::PTR).csr.write(0);
+    let nvic = &*NVIC::PTR;
+    for (icer, icpr) in nvic.icer.iter().zip(nvic.icpr.iter()) {
+        icer.write(u32::MAX);
+        icpr.write(u32::MAX);
+    }
+    let scb = &*SCB::PTR;
+    scb.icsr.write(ICSR_CLEAR_PENDING);
+
+    // 2) Privileged thread mode on MSP, no lazy FP state carried over. The
+    //    bootloader never leaves this state, so normally this changes nothing
+    let mut ctrl = control::read();
+    ctrl.set_spsel(Spsel::Msp);
+    ctrl.set_npriv(Npriv::Privileged);
+    ctrl.set_fpca(Fpca::NotActive);
+    control::write(ctrl);
+    cortex_m::asm::isb();
+
+    // 3) Set VTOR to point to the application's vector table using cortex-m API
+    scb.vtor.write(addr);
+
+    // Memory barriers
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    // PRIMASK as out of reset; every line is disabled, so nothing fires
+    cortex_m::interrupt::enable();
+
+    // 4) Load the app's stack pointer and jump to its reset handler
+    let vector_table = addr as *const u32;
+    let msp = core::ptr::read_volatile(vector_table);
+    let reset = core::ptr::read_volatile(vector_table.add(1));
+    cortex_m::asm::bootstrap(msp as *const u32, reset as *const u32)
+}
+
+/// Test double for the QEMU harness
+///
+/// Prints the address the bootloader would have jumped to over semihosting and
+/// then ends the emulation, so a script can assert on the selected slot.
+#[cfg(feature = "qemu")]
+pub struct SemihostingJumper;
+
+#[cfg(feature = "qemu")]
+impl Jumper for SemihostingJumper {
+    fn jump(&self, addr: u32) -> ! {
+        use cortex_m_semihosting::{debug, hprintln};
+
+        hprintln!("jump {:#010x}", addr);
+        debug::exit(debug::EXIT_SUCCESS);
+
+        // Only reached if the host ignored the exit request
+        loop {
+            cortex_m::asm::bkpt();
+        }
+    }
+}
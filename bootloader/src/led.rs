@@ -0,0 +1,51 @@
+//! The Blackpill's on-board LED (PC13, active low), for bootloader status codes
+//!
+//! The output is preset high (LED off) before the pin is switched to output,
+//! so claiming it doesn't flash the LED.
+//!
+//! Only the paths that end in the bootloader (self-test, no bootable image)
+//! claim the LED. A normal boot never constructs a [`Led`], so PC13 reaches
+//! the app in its reset state (floating input, GPIOC clock off) and a
+//! successful boot shows no LED activity at all.
+
+use crate::delay_ms;
+use stm32f4::stm32f411 as pac;
+
+pub struct Led {
+    gpioc: pac::GPIOC,
+}
+
+impl Led {
+    /// Clocks GPIOC and makes PC13 a push-pull output, LED off
+    ///
+    /// Call only once the bootloader knows it won't hand over to an app.
+    pub fn init() -> Self {
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        let gpioc = unsafe { pac::GPIOC::steal() };
+
+        rcc.ahb1enr().modify(|_, w| w.gpiocen().set_bit());
+        gpioc.bsrr().write(|w| unsafe { w.bits(1 << 13) });
+        gpioc
+            .moder()
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << 26)) | (0b01 << 26)) });
+        Self { gpioc }
+    }
+
+    pub fn on(&mut self) {
+        self.gpioc
+            .bsrr()
+            .write(|w| unsafe { w.bits(1 << (13 + 16)) });
+    }
+
+    pub fn off(&mut self) {
+        self.gpioc.bsrr().write(|w| unsafe { w.bits(1 << 13) });
+    }
+
+    /// One on/off cycle
+    pub fn blink(&mut self, on_ms: u32, off_ms: u32) {
+        self.on();
+        delay_ms(on_ms);
+        self.off();
+        delay_ms(off_ms);
+    }
+}
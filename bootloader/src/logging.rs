@@ -0,0 +1,41 @@
+//! Leveled log macros, backed by defmt over RTT with the `defmt` feature (or
+//! over USART2 with `boot-console`, see `console`)
+//!
+//! Without the feature every macro expands to nothing, so the default build
+//! carries no logging code at all. Levels are used consistently:
+//!
+//! - `error!`: a slot failed validation, or nothing can be booted
+//! - `warn!`: the bootloader didn't do what was asked (fallback, storm)
+//! - `info!`: normal decisions (reset cause, slot chosen, mode entered)
+//! - `debug!`: details of the hand-over itself
+//!
+//! The compile-time filter is defmt's `DEFMT_LOG`, which `build.rs` derives
+//! from the `log-*` features (`info` if none is picked).
+
+macro_rules! error {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::error!($($arg)*);
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::warn!($($arg)*);
+    }};
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::info!($($arg)*);
+    }};
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::debug!($($arg)*);
+    }};
+}
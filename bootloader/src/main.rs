@@ -1,62 +1,336 @@
 #![no_std]
 #![no_main]
 
-use core::ptr::{read_volatile, write_volatile};
+#[macro_use]
+mod logging;
+
+#[cfg(feature = "secure-boot")]
+mod auth;
+mod bor;
+#[cfg(feature = "boot-console")]
+mod console;
+mod delta;
+mod flash;
+#[cfg(feature = "heartbeat")]
+mod heartbeat;
+mod info;
+mod jump;
+mod led;
+mod persist;
+#[cfg(feature = "clear-ram")]
+mod ram;
+mod reset;
+#[cfg(feature = "anti-rollback")]
+mod rollback;
+#[cfg(feature = "secure-boot")]
+mod secure;
+mod selftest;
+mod serial;
+mod slot;
+#[cfg(feature = "switch-guard")]
+mod storm;
+#[cfg(feature = "boot-strap")]
+mod strap;
+mod update;
+#[cfg(feature = "verify-cache")]
+mod verify_cache;
+mod vtor_check;
+mod xmodem;
+mod ymodem;
+
+use boot_protocol::{BootRequest, BootTarget, MagicStore};
 use cortex_m_rt::entry;
+#[cfg(all(feature = "defmt", not(feature = "boot-console")))]
+use defmt_rtt as _;
+use jump::Jumper;
 use panic_halt as _;
 // Import stm32f4 to provide interrupt vector table
 use stm32f4 as _;
 
 // Magic value stored in noinit section (survives reset)
+#[cfg(not(feature = "rtc-backup"))]
 #[link_section = ".noinit"]
-static mut MAGIC_VALUE: u32 = 0;
+static MAGIC_VALUE: boot_protocol::NoinitCell<u32> = unsafe { boot_protocol::NoinitCell::new() };
+
+/// Core clock while the bootloader runs: HSI, the clock tree is never touched
+const CORE_HZ: u32 = 16_000_000;
+
+/// Brown-out threshold the option bytes are kept at: flash is programmed with
+/// x32 parallelism, which is specified from 2.7 V up
+const BOR_LEVEL: bor::BorLevel = bor::BorLevel::Level3;
+
+/// Pause before jumping, gives a debugger time to attach and halt before the
+/// app reconfigures the clocks. 0 (the default) compiles the wait out.
+const BOOT_DELAY_MS: u32 = 0;
+
+/// Slot a pinned SKU always boots (`force-app1`/`force-app2` features),
+/// `None` to pick it from the boot request as usual
+const FORCE_SLOT: Option<BootTarget> = if cfg!(feature = "force-app1") {
+    Some(BootTarget::App1)
+} else if cfg!(feature = "force-app2") {
+    Some(BootTarget::App2)
+} else {
+    None
+};
+
+#[cfg(all(feature = "force-app1", feature = "force-app2"))]
+compile_error!("enable at most one of the force-app1 and force-app2 features");
+
+/// Busy-waits for roughly `ms` milliseconds, no timer needed
+fn delay_ms(ms: u32) {
+    cortex_m::asm::delay(CORE_HZ / 1000 * ms);
+}
+
+/// Starts the DWT cycle counter, which flash waits and `auth` rely on
+fn enable_cycle_counter() {
+    let mut core = unsafe { cortex_m::Peripherals::steal() };
+    core.DCB.enable_trace();
+    cortex_m::peripheral::DWT::unlock();
+    core.DWT.enable_cycle_counter();
+}
+
+/// Whether a slot holds an image the bootloader is willing to jump into
+fn slot_bootable(target: BootTarget) -> bool {
+    if !slot::is_valid_app(target) {
+        error!("slot {=u32:#010x}: no valid vector table", target.addr());
+        return false;
+    }
+    // Before the cache shortcut, the version may have been raised since
+    #[cfg(feature = "anti-rollback")]
+    if !rollback::slot_version_allowed(target) {
+        error!(
+            "slot {=u32:#010x}: no header or version below the minimum",
+            target.addr()
+        );
+        return false;
+    }
+    // Verified on an earlier boot and unchanged since
+    #[cfg(feature = "verify-cache")]
+    if !verify_cache::needs_full_verify(target) {
+        debug!(
+            "slot {=u32:#010x}: verified before, skipping",
+            target.addr()
+        );
+        return true;
+    }
+    #[cfg(feature = "verify-cache")]
+    if !verify_cache::image_crc_ok(target) {
+        error!("slot {=u32:#010x}: image CRC mismatch", target.addr());
+        return false;
+    }
+    // Unsigned or tampered images are refused outright
+    #[cfg(feature = "secure-boot")]
+    if !secure::slot_signed(target) {
+        error!("slot {=u32:#010x}: signature rejected", target.addr());
+        return false;
+    }
+    #[cfg(feature = "verify-cache")]
+    verify_cache::record_verified(target);
+    true
+}
 
-// Magic values for app selection
-const MAGIC_APP1: u32 = 0xDEAD_BEEF;
-const MAGIC_APP2: u32 = 0xCAFE_BABE;
+/// Counts an app switch, logging on USART2 if switches are storming
+#[cfg(feature = "switch-guard")]
+fn switch_storm() -> bool {
+    use core::fmt::Write;
 
-// Application base addresses (after 16KB bootloader)
-const APP1_ADDR: u32 = 0x0800_4000; // 16KB offset
-const APP2_ADDR: u32 = 0x0802_4000; // 16KB + 128KB offset
+    // The window lives in the backup registers
+    unsafe { boot_protocol::enable_backup_access() };
+    if !storm::detect_switch_storm() {
+        return false;
+    }
+
+    warn!("switch storm detected, ignoring switch requests");
+    let mut serial = serial::BootSerial::init();
+    writeln!(serial, "\r\nBOOT: switch storm detected, booting App1\r").ok();
+    serial.flush();
+    true
+}
 
-/// Jumps to an application at the given address
+/// Nothing to boot: report it and stay in the bootloader
 ///
-/// # Safety
-/// This must point to a valid application with proper vector table
-unsafe fn jump_to_app(addr: u32) -> ! {
-    // Set VTOR to point to the application's vector table using cortex-m API
-    let scb = &*cortex_m::peripheral::SCB::PTR;
-    scb.vtor.write(addr);
+/// First place on the boot path that touches the LED, see `led`.
+fn halt_no_image() -> ! {
+    error!("no bootable image");
 
-    // Memory barriers
-    cortex_m::asm::dsb();
-    cortex_m::asm::isb();
+    #[cfg(feature = "qemu")]
+    {
+        cortex_m_semihosting::hprintln!("no bootable image");
+        cortex_m_semihosting::debug::exit(cortex_m_semihosting::debug::EXIT_FAILURE);
+    }
 
-    // Jump to the application using bootload
-    cortex_m::asm::bootload(addr as *const u32)
+    // Same fast blink as a failed self-test
+    let mut led = led::Led::init();
+    loop {
+        led.blink(100, 100);
+    }
 }
 
-#[entry]
-fn main() -> ! {
-    // Read the magic value from noinit RAM using raw pointer
-    let magic_ptr = unsafe { core::ptr::addr_of!(MAGIC_VALUE) as *const u32 };
-    let magic = unsafe { read_volatile(magic_ptr) };
+/// At power-on, the slot picked by the strap pins (see `strap`), if any
+#[cfg(feature = "boot-strap")]
+fn strapped_slot() -> Option<BootTarget> {
+    if boot_protocol::stashed_reset_cause() != boot_protocol::ResetCause::PowerOn {
+        return None;
+    }
+    let value = strap::read_strap(&strap::STRAP_PINS);
+    let target = strap::strap_slot(value);
+    if let Some(target) = target {
+        info!(
+            "strap {=u16} selects slot {=u32:#010x}",
+            value,
+            target.addr()
+        );
+    }
+    target
+}
 
-    // Clear the magic value so default boot works after power cycle
-    let magic_ptr_mut = unsafe { core::ptr::addr_of_mut!(MAGIC_VALUE) };
-    unsafe {
-        write_volatile(magic_ptr_mut, 0);
+#[cfg(not(feature = "boot-strap"))]
+fn strapped_slot() -> Option<BootTarget> {
+    None
+}
+
+/// Consumes the pending boot request and hands control to the chosen app
+fn boot<S: MagicStore, J: Jumper>(store: &mut S, jumper: &J) -> ! {
+    let magic = store.read();
+
+    // Settings lost with the power come back from flash, before update mode
+    // or the apps (through `SlotRoles::load`) read them
+    persist::restore();
+
+    // Before the service requests, which clear the suspicion: an update may
+    // well be what fixes the slot
+    #[cfg(feature = "heartbeat")]
+    let suspect = heartbeat::take_suspect_slot();
+    #[cfg(not(feature = "heartbeat"))]
+    let suspect = None;
+
+    // Clear the magic value so default boot works after power cycle, also
+    // when an update started from it fails half way
+    store.clear();
+
+    match BootRequest::from_magic(magic) {
+        // Manufacturing gate: report on both slots instead of booting
+        Some(BootRequest::SelfTest) => {
+            info!("self-test requested");
+            selftest::run_and_halt()
+        }
+        // Stay resident and wait for the host
+        Some(BootRequest::UpdateMode) => {
+            info!("entering update mode");
+            update::run()
+        }
+        _ => {}
     }
 
-    // Decide which app to boot based on magic value
-    let app_addr = match magic {
-        MAGIC_APP2 => APP2_ADDR,
-        MAGIC_APP1 => APP1_ADDR,
-        _ => APP1_ADDR, // Default to App1
+    // Without a request the operator's default (`default N` in update mode),
+    // or else the slot playing App1's role (`swap`); a pinned SKU only ever
+    // boots the configured slot
+    let roles = boot_protocol::SlotRoles::load();
+    let policy = bootloader_core::BootPolicy {
+        default: strapped_slot()
+            .or_else(persist::load_persisted_slot)
+            .unwrap_or(roles.slot(bootloader_core::RECOVERY_SLOT)),
+        roles,
+        forced: FORCE_SLOT,
+        suspect,
     };
+    if suspect.is_some() {
+        warn!(
+            "slot {=u32:#010x} never reached its main loop, preferring the other",
+            suspect.map_or(0, BootTarget::addr)
+        );
+    }
+
+    // Apps bouncing between each other: ignore switches for a while
+    #[cfg(feature = "switch-guard")]
+    let storm = switch_storm;
+    #[cfg(not(feature = "switch-guard"))]
+    let storm = || false;
+
+    // Falls back to the other slot if the requested one is rejected
+    let mut rejected = None;
+    let decision = bootloader_core::decide_boot(magic, &policy, storm, |target| {
+        let ok = slot_bootable(target);
+        if !ok {
+            rejected.get_or_insert(target);
+        }
+        ok
+    });
+
+    match decision {
+        Ok(addr) => {
+            // Only the requested slot can be rejected on the way to a boot
+            if rejected.is_some() {
+                warn!(
+                    "slot {=u32:#010x} rejected, falling back to {=u32:#010x}",
+                    rejected.map_or(0, BootTarget::addr),
+                    addr
+                );
+            }
+            launch(jumper, addr)
+        }
+        Err(bootloader_core::BootError::NoBootableImage) => halt_no_image(),
+    }
+}
+
+/// Hands control to the chosen (already validated) slot at `addr`
+fn launch<J: Jumper>(jumper: &J, addr: u32) -> ! {
+    if BOOT_DELAY_MS > 0 {
+        delay_ms(BOOT_DELAY_MS);
+    }
+
+    // The app has to prove it came up, see `heartbeat`
+    #[cfg(feature = "heartbeat")]
+    if let Some(target) = BootTarget::containing(addr) {
+        boot_protocol::arm_heartbeat(target);
+    }
+
+    // Jump to the selected application. Logged here, before RAM is cleared:
+    // the logger's state goes with it
+    info!("booting slot {=u32:#010x}", addr);
+    debug!(
+        "jump: msp {=u32:#010x}, reset {=u32:#010x}",
+        unsafe { (addr as *const u32).read_volatile() },
+        unsafe { (addr as *const u32).add(1).read_volatile() }
+    );
 
-    // Jump to the selected application
+    // Last, nothing of the bootloader's may be used after it
+    #[cfg(feature = "clear-ram")]
     unsafe {
-        jump_to_app(app_addr);
+        ram::clear_ram_except_noinit();
     }
+    jumper.jump(addr)
+}
+
+#[entry]
+fn main() -> ! {
+    // Before the first log line, which would otherwise be dropped
+    #[cfg(feature = "boot-console")]
+    console::init_boot_console();
+
+    // First, while RCC_CSR still holds this boot's flags: the reset after a
+    // reprogram adds SFTRSTF to them rather than hiding the original cause
+    bor::ensure_bor_level(BOR_LEVEL);
+
+    // Let the app find out why we booted, RCC_CSR is cleared after this
+    let cause = reset::take_reset_cause();
+    info!("reset cause: {=str}", cause.as_str());
+    boot_protocol::stash_reset_cause(cause);
+
+    // The magic lives in the bootloader's own noinit word
+    #[cfg(not(feature = "rtc-backup"))]
+    let mut store = boot_protocol::NoinitStore::new(&MAGIC_VALUE);
+    // ...or in RTC_BKP0R, which has to be unlocked before it can be cleared
+    #[cfg(feature = "rtc-backup")]
+    let mut store = unsafe {
+        boot_protocol::enable_backup_access();
+        boot_protocol::RtcBackupStore::new()
+    };
+
+    #[cfg(not(feature = "qemu"))]
+    let jumper = jump::CortexMJumper;
+    #[cfg(feature = "qemu")]
+    let jumper = jump::SemihostingJumper;
+
+    boot(&mut store, &jumper)
 }
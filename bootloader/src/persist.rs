@@ -0,0 +1,113 @@
+//! Settings kept across boots in `RTC_BKP5R`, backed by the settings log
+//!
+//! One tagged word, past the verify cache's BKP3R/BKP4R. The tag tells a
+//! record written here apart from a backup register that was reset (zero)
+//! or never written; without a valid record every setting reads as unset.
+//! The apps read the slot roles from it as well, so its address, tag and
+//! role bit live in `boot_protocol` (see `SlotRoles`).
+//!
+//! The backup domain survives resets, and power cycles only while VBAT is
+//! supplied (a coin cell on the VBAT pin). So every record stored also goes
+//! to the settings log in flash (see `bootloader_core::latest_record`), and
+//! on the way up [`restore`] copies the newest one back into an emptied
+//! `RTC_BKP5R`. The register stays the copy everything reads, the log is its
+//! backing store.
+//!
+//! The log takes one word per change and is only emptied by reflashing the
+//! bootloader. Once it is full a change is still made, in the register only,
+//! and is lost with the power again on a board without VBAT.
+
+use crate::flash::{self, FlashError};
+use boot_protocol::{BootTarget, SlotRoles, SETTINGS_ROLES_SWAPPED};
+use core::ptr::{read_volatile, write_volatile};
+
+const RECORD: *mut u32 = boot_protocol::SETTINGS_ADDR as *mut u32; // RTC_BKP5R
+
+// Upper half of a valid record
+const TAG: u32 = boot_protocol::SETTINGS_TAG;
+const TAG_MASK: u32 = 0xFFFF_0000;
+
+// Default slot, as its 1-based index (0: unset)
+const SLOT_MASK: u32 = 0x0000_000F;
+
+fn is_record(word: u32) -> bool {
+    word & TAG_MASK == TAG
+}
+
+fn load() -> Option<u32> {
+    let record = unsafe { read_volatile(RECORD) };
+    if is_record(record) {
+        return Some(record);
+    }
+    bootloader_core::latest_record(flash::settings_log(), is_record)
+}
+
+fn write_register(record: u32) {
+    unsafe {
+        boot_protocol::enable_backup_access();
+        write_volatile(RECORD, record);
+    }
+}
+
+/// Stores `record` in the register and, unless it is the newest there
+/// already, appends it to the log
+///
+/// The register is written either way, so the change applies even when the
+/// log can't take it; the error says it won't survive losing the power.
+fn store(record: u32) -> Result<(), FlashError> {
+    let record = TAG | (record & !TAG_MASK);
+    write_register(record);
+
+    let log = flash::settings_log();
+    if bootloader_core::latest_record(log, is_record) == Some(record) {
+        return Ok(());
+    }
+    let index = bootloader_core::next_free(log).ok_or(FlashError::LogFull)?;
+    flash::write_settings_word(index, record)
+}
+
+/// Refills an emptied `RTC_BKP5R` from the settings log, e.g. after a power
+/// cycle without VBAT. Called on the way up, before anything reads it
+pub fn restore() {
+    if is_record(unsafe { read_volatile(RECORD) }) {
+        return;
+    }
+    if let Some(record) = bootloader_core::latest_record(flash::settings_log(), is_record) {
+        info!("settings {=u32:#010x} restored from flash", record);
+        write_register(record);
+    }
+}
+
+/// Slot booted when no app asked for one, `None` if never set
+pub fn load_persisted_slot() -> Option<BootTarget> {
+    match load()? & SLOT_MASK {
+        1 => Some(BootTarget::App1),
+        2 => Some(BootTarget::App2),
+        _ => None,
+    }
+}
+
+/// Makes `target` the slot booted when no app asks for one
+///
+/// Only read on the way up, so it takes effect on the next boot without a
+/// request, never on the current one. An error means it is kept in the
+/// register only, see the module docs.
+pub fn persist_slot(target: BootTarget) -> Result<(), FlashError> {
+    let index = match target {
+        BootTarget::App1 => 1,
+        BootTarget::App2 => 2,
+    };
+    let record = load().unwrap_or(0);
+    store((record & !SLOT_MASK) | index)
+}
+
+/// Swaps which slot plays App1's role and which App2's, returning the new
+/// mapping and whether the log took it (as for [`persist_slot`])
+///
+/// Nothing in flash moves. Like the default slot it is only read on the way
+/// up, so it takes effect from the next boot.
+pub fn swap_slot_roles() -> (SlotRoles, Result<(), FlashError>) {
+    let record = load().unwrap_or(0);
+    let stored = store(record ^ SETTINGS_ROLES_SWAPPED);
+    (SlotRoles::load(), stored)
+}
@@ -0,0 +1,48 @@
+//! Zeroing RAM before the jump, for a cold-boot-like start (`clear-ram`)
+//!
+//! A jump follows a warm reset, so without this the app finds RAM as the
+//! previous app left it. Anything it reads before writing (a stack variable,
+//! a `MaybeUninit`) then tends to hold a plausible old value instead of the
+//! garbage a cold boot gives, and the bug only shows in the field.
+
+extern "C" {
+    // sections.x: the bootloader's RAM, everything below the reserved words
+    static _clearable_ram_start: u32;
+    static _clearable_ram_end: u32;
+}
+
+/// Zeroes RAM up to the current stack pointer, leaving the reserved words at
+/// the top (boot magic, reset cause, heartbeat, panic ring) alone
+///
+/// The frames above the stack pointer are the bootloader's own and stay in
+/// place; the app's stack starts on top of them anyway. Everything else the
+/// bootloader keeps in RAM is gone afterwards, so call it last thing before
+/// the jump: no logging, no statics.
+///
+/// # Safety
+/// Nothing below the caller's stack frame may be used again, including any
+/// static of the bootloader's; interrupts must be off or not in use.
+pub unsafe fn clear_ram_except_noinit() {
+    let start = core::ptr::addr_of!(_clearable_ram_start) as u32;
+    let end = core::ptr::addr_of!(_clearable_ram_end) as u32;
+    // The reserved words are outside RAM already, the stack is what's left
+    let sp = cortex_m::register::msp::read() & !3;
+    let end = end.min(sp).min(boot_protocol::PANIC_LOG_ADDR);
+
+    // A loop of word stores kept in registers. Not `write_bytes`: with a
+    // runtime length that is a call to `memset`, which saves its registers
+    // and return address right below `sp`, inside the range being zeroed
+    if end > start {
+        core::arch::asm!(
+            "2:",
+            "str {zero}, [{addr}], #4",
+            "cmp {addr}, {end}",
+            "blo 2b",
+            addr = inout(reg) start => _,
+            end = in(reg) end,
+            zero = in(reg) 0u32,
+            options(nostack),
+        );
+    }
+    cortex_m::asm::dsb();
+}
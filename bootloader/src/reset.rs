@@ -0,0 +1,38 @@
+//! Decoding why the chip came out of reset
+
+use boot_protocol::ResetCause;
+use stm32f4::stm32f411 as pac;
+
+// RCC_CSR reset flags
+const BORRSTF: u32 = 1 << 25;
+const PINRSTF: u32 = 1 << 26;
+const PORRSTF: u32 = 1 << 27;
+const SFTRSTF: u32 = 1 << 28;
+const IWDGRSTF: u32 = 1 << 29;
+const WWDGRSTF: u32 = 1 << 30;
+
+/// Maps the `RCC_CSR` flags to a single cause
+///
+/// Every reset also asserts the pin flag (the reset circuit drives NRST), so
+/// the more specific flags are checked first and `Pin` is the fallback.
+fn decode(csr: u32) -> ResetCause {
+    if csr & (PORRSTF | BORRSTF) != 0 {
+        ResetCause::PowerOn
+    } else if csr & (IWDGRSTF | WWDGRSTF) != 0 {
+        ResetCause::Watchdog
+    } else if csr & SFTRSTF != 0 {
+        ResetCause::Software
+    } else if csr & PINRSTF != 0 {
+        ResetCause::Pin
+    } else {
+        ResetCause::Unknown
+    }
+}
+
+/// Reads the reset flags and clears them, so the next boot sees fresh ones
+pub fn take_reset_cause() -> ResetCause {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let cause = decode(rcc.csr().read().bits());
+    rcc.csr().modify(|_, w| w.rmvf().set_bit());
+    cause
+}
@@ -0,0 +1,30 @@
+//! Anti-rollback: refusing images older than an approved version
+//! (`anti-rollback` feature)
+//!
+//! A slot is only booted if its header's version is at least [`MIN_VERSION`].
+//! A slot without a header has no version to check and is refused too. A
+//! refused slot is handled like any other rejected one: the bootloader falls
+//! back to the other slot, the recovery slot for an app request.
+//!
+//! With `secure-boot` the signature covers the version (see `crate::secure`),
+//! so an old signed image can't be passed off as a newer one by editing its
+//! header. Without it nothing but the CRC vouches for the image, and the CRC
+//! leaves the header out: the gate then stops an old image being flashed by
+//! mistake, not someone who edits the version field.
+
+use crate::slot;
+use boot_protocol::{pack_version, BootTarget, SlotHeader};
+
+/// Oldest image version the bootloader boots, raise it with each release
+/// that old images must not come back from
+pub const MIN_VERSION: u32 = pack_version(0, 1, 0);
+
+/// Whether `header`'s version is at least [`MIN_VERSION`]
+pub fn version_allowed(header: &SlotHeader) -> bool {
+    header.version >= MIN_VERSION
+}
+
+/// Whether the slot has a header whose version passes [`version_allowed`]
+pub fn slot_version_allowed(target: BootTarget) -> bool {
+    slot::read_header(target).is_some_and(|header| version_allowed(&header))
+}
@@ -0,0 +1,55 @@
+//! Ed25519 signature check of slot images (`secure-boot` feature)
+//!
+//! Images are signed with Ed25519ph: the signature covers the SHA-512 of the
+//! same bytes as the header CRC (the whole image minus the header), which
+//! lets the bootloader hash the two halves in place instead of copying the
+//! image into RAM, followed by the header's `version` and `image_len` (see
+//! `bootloader_core::signed_fields`). A version edited to get past the
+//! anti-rollback gate then fails the signature. The public key is baked in
+//! at build time, see `build.rs`.
+
+use crate::slot;
+use boot_protocol::{BootTarget, SlotHeader};
+use salty::{PublicKey, Sha512, Signature};
+
+pub const PUBLIC_KEY: &[u8; 32] = include_bytes!(concat!(env!("OUT_DIR"), "/boot_public_key.bin"));
+
+/// Verifies the header's signature over the image starting at `addr`
+///
+/// `header` must belong to the slot at `addr` and have passed
+/// `slot::header_len_ok`.
+pub fn verify_signature(addr: u32, header: &SlotHeader) -> bool {
+    let Some(target) = [BootTarget::App1, BootTarget::App2]
+        .into_iter()
+        .find(|target| target.addr() == addr)
+    else {
+        return false;
+    };
+
+    let Ok(key) = PublicKey::try_from(PUBLIC_KEY) else {
+        return false;
+    };
+
+    let (before, after) = slot::image_parts(target, header);
+    let mut hash = Sha512::new();
+    hash.update(before);
+    hash.update(after);
+    hash.update(&bootloader_core::signed_fields(header));
+    let digest = hash.finalize();
+
+    key.verify_prehashed(&digest, &Signature::from(&header.signature), None)
+        .is_ok()
+}
+
+/// Whether the slot carries a stamped header with a valid signature
+///
+/// Unsigned or unstamped images are rejected, as is anything whose header
+/// length doesn't fit the slot.
+pub fn slot_signed(target: BootTarget) -> bool {
+    match slot::read_header(target) {
+        Some(header) if header.is_stamped() && slot::header_len_ok(target, &header) => {
+            verify_signature(target.addr(), &header)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,196 @@
+//! End-of-line self-test: checks both slots and reports instead of booting
+//!
+//! Requested with `MAGIC_SELF_TEST`. Besides the slots it checks that
+//! interrupts follow VTOR (see `vtor_check`), which the jump depends on. The
+//! summary goes out on USART2 and the LED then loops a result code forever:
+//! slow blink for pass, fast for fail.
+
+use crate::led::Led;
+use crate::serial::BootSerial;
+use crate::slot;
+use crate::vtor_check;
+use boot_protocol::{unpack_version, BootTarget, SlotHeader};
+use core::fmt::{self, Write};
+
+/// Outcome of checking a single slot
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SlotStatus {
+    Pass,
+    /// Slot is erased, acceptable if the slot is optional
+    Empty,
+    BadVectors,
+    NoHeader,
+    /// Header present but length/CRC never stamped
+    Unstamped,
+    BadLength,
+    CrcMismatch,
+    /// CRC fine but the Ed25519 signature doesn't verify
+    #[cfg(feature = "secure-boot")]
+    BadSignature,
+}
+
+pub struct SlotReport {
+    pub target: BootTarget,
+    pub status: SlotStatus,
+    pub header: Option<SlotHeader>,
+    /// CRC actually computed over the image, if it got that far
+    pub crc: Option<u32>,
+}
+
+impl SlotReport {
+    pub fn passed(&self) -> bool {
+        match self.status {
+            SlotStatus::Pass => true,
+            SlotStatus::Empty => is_optional(self.target),
+            _ => false,
+        }
+    }
+}
+
+pub struct SelfTestReport {
+    pub slots: [SlotReport; 2],
+    /// An interrupt dispatched through a relocated vector table
+    pub vtor_relocation: bool,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.vtor_relocation && self.slots.iter().all(SlotReport::passed)
+    }
+}
+
+/// App1 is the default image and must be present, App2 may be left empty
+fn is_optional(target: BootTarget) -> bool {
+    target == BootTarget::App2
+}
+
+fn check_slot(target: BootTarget) -> SlotReport {
+    let mut report = SlotReport {
+        target,
+        status: SlotStatus::Pass,
+        header: None,
+        crc: None,
+    };
+
+    if slot::is_erased(target) {
+        report.status = SlotStatus::Empty;
+        return report;
+    }
+    if !slot::is_valid_app(target) {
+        report.status = SlotStatus::BadVectors;
+        return report;
+    }
+
+    let Some(header) = slot::read_header(target) else {
+        report.status = SlotStatus::NoHeader;
+        return report;
+    };
+    report.header = Some(header);
+
+    if !header.is_stamped() {
+        report.status = SlotStatus::Unstamped;
+    } else if !slot::header_len_ok(target, &header) {
+        report.status = SlotStatus::BadLength;
+    } else {
+        let crc = slot::image_crc(target, &header);
+        report.crc = Some(crc);
+        if crc != header.crc32 {
+            report.status = SlotStatus::CrcMismatch;
+        } else {
+            #[cfg(feature = "secure-boot")]
+            if !crate::secure::verify_signature(target.addr(), &header) {
+                report.status = SlotStatus::BadSignature;
+            }
+        }
+    }
+    report
+}
+
+/// Validates both slots, reading their headers and checking their CRCs, and
+/// the vector table relocation
+pub fn run_self_test() -> SelfTestReport {
+    SelfTestReport {
+        slots: [check_slot(BootTarget::App1), check_slot(BootTarget::App2)],
+        vtor_relocation: vtor_check::relocation_works(),
+    }
+}
+
+/// Runs the self-test, prints the report and blinks the result code forever
+pub fn run_and_halt() -> ! {
+    let report = run_self_test();
+
+    let mut serial = BootSerial::init();
+    write!(serial, "{}", report).ok();
+    serial.flush();
+
+    let mut led = Led::init();
+    loop {
+        if report.passed() {
+            led.blink(1000, 1000);
+        } else {
+            led.blink(100, 100);
+        }
+    }
+}
+
+impl fmt::Display for SlotStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SlotStatus::Pass => "PASS",
+            SlotStatus::Empty => "EMPTY",
+            SlotStatus::BadVectors => "FAIL bad vector table",
+            SlotStatus::NoHeader => "FAIL no header",
+            SlotStatus::Unstamped => "FAIL header not stamped",
+            SlotStatus::BadLength => "FAIL bad image length",
+            SlotStatus::CrcMismatch => "FAIL crc mismatch",
+            #[cfg(feature = "secure-boot")]
+            SlotStatus::BadSignature => "FAIL bad signature",
+        })
+    }
+}
+
+impl fmt::Display for SlotReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} @ {:#010x}: {}",
+            self.target,
+            self.target.addr(),
+            self.status
+        )?;
+        if self.status == SlotStatus::Empty {
+            let note = if is_optional(self.target) {
+                "optional"
+            } else {
+                "required"
+            };
+            write!(f, " ({})", note)?;
+        }
+        if let Some(header) = &self.header {
+            let (major, minor, patch) = unpack_version(header.version);
+            write!(f, " v{}.{}.{}", major, minor, patch)?;
+            if header.is_stamped() {
+                write!(f, " len={} crc={:#010x}", header.image_len, header.crc32)?;
+            }
+        }
+        if let Some(crc) = self.crc {
+            if self.status == SlotStatus::CrcMismatch {
+                write!(f, " computed={:#010x}", crc)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\r\n=== BOOTLOADER SELF-TEST ===\r\n")?;
+        for slot in &self.slots {
+            write!(f, "{}\r\n", slot)?;
+        }
+        let vtor = if self.vtor_relocation { "PASS" } else { "FAIL" };
+        write!(f, "VTOR relocation: {}\r\n", vtor)?;
+        let result = if self.passed() { "PASS" } else { "FAIL" };
+        write!(f, "RESULT: {}\r\n", result)
+    }
+}
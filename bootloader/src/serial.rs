@@ -0,0 +1,130 @@
+//! Polled USART2 driver for bootloader diagnostics
+//!
+//! Uses the same pins and settings as the apps (PA2 = TX, PA3 = RX, 115200 8N1)
+//! so one serial adapter covers every image. The bootloader never touches the
+//! clock tree, so the baud divisor is computed for the 16 MHz HSI.
+
+use crate::CORE_HZ;
+use stm32f4::stm32f411 as pac;
+
+const BAUD: u32 = 115_200;
+
+pub struct BootSerial {
+    usart: pac::USART2,
+}
+
+impl BootSerial {
+    /// Clocks GPIOA and USART2, routes PA2/PA3 to AF7 and enables TX/RX
+    pub fn init() -> Self {
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        let gpioa = unsafe { &*pac::GPIOA::ptr() };
+        let usart = unsafe { pac::USART2::steal() };
+
+        rcc.ahb1enr().modify(|_, w| w.gpioaen().set_bit());
+        rcc.apb1enr().modify(|_, w| w.usart2en().set_bit());
+
+        // PA2/PA3: MODER = 0b10 (alternate), AFRL = 7 (USART2)
+        gpioa
+            .moder()
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0xF << 4)) | (0xA << 4)) });
+        gpioa
+            .afrl()
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0xFF << 8)) | (0x77 << 8)) });
+
+        usart
+            .brr()
+            .write(|w| unsafe { w.bits((CORE_HZ + BAUD / 2) / BAUD) });
+        usart
+            .cr1()
+            .write(|w| w.ue().set_bit().te().set_bit().re().set_bit());
+
+        Self { usart }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        while self.usart.sr().read().txe().bit_is_clear() {}
+        self.usart.dr().write(|w| unsafe { w.bits(byte as u32) });
+    }
+
+    /// Blocks until a byte arrives
+    ///
+    /// An overrun (ORE) is cleared by the same SR-then-DR read sequence, so a
+    /// byte dropped while nobody was listening doesn't wedge the receiver.
+    pub fn read_byte(&mut self) -> u8 {
+        loop {
+            let sr = self.usart.sr().read();
+            if sr.rxne().bit_is_set() || sr.ore().bit_is_set() {
+                return self.usart.dr().read().bits() as u8;
+            }
+        }
+    }
+
+    /// Like `read_byte`, but `None` for a byte received with a framing, noise
+    /// or parity error
+    ///
+    /// The same SR-then-DR read clears those flags too, so a glitchy line
+    /// (a cable being plugged in) costs the damaged bytes and nothing more.
+    pub fn read_clean_byte(&mut self) -> Option<u8> {
+        loop {
+            let sr = self.usart.sr().read();
+            if sr.rxne().bit_is_set() || sr.ore().bit_is_set() {
+                let byte = self.usart.dr().read().bits() as u8;
+                let damaged = sr.fe().bit_is_set() || sr.nf().bit_is_set() || sr.pe().bit_is_set();
+                return (!damaged).then_some(byte);
+            }
+        }
+    }
+
+    /// Like `read_byte`, but gives up after about `timeout_ms`
+    pub fn read_byte_timeout(&mut self, timeout_ms: u32) -> Option<u8> {
+        // Polled every 10us, well inside one byte time at 115200 baud
+        for _ in 0..timeout_ms * 100 {
+            let sr = self.usart.sr().read();
+            if sr.rxne().bit_is_set() || sr.ore().bit_is_set() {
+                return Some(self.usart.dr().read().bits() as u8);
+            }
+            cortex_m::asm::delay(CORE_HZ / 100_000);
+        }
+        None
+    }
+
+    /// Blocks until the last byte has left the shift register
+    pub fn flush(&mut self) {
+        while self.usart.sr().read().tc().bit_is_clear() {}
+    }
+}
+
+/// Typed to leave `uart_echo_loop`
+pub const ECHO_EXIT: &[u8] = b"+++";
+
+/// Echoes every byte received back until [`ECHO_EXIT`] arrives
+///
+/// For checking the wiring: anything typed should come straight back.
+/// Damaged bytes are dropped rather than echoed, see `read_clean_byte`.
+pub fn uart_echo_loop(uart: &mut BootSerial) {
+    use core::fmt::Write;
+
+    writeln!(uart, "echo mode, type +++ to leave\r").ok();
+    let mut matched = 0;
+    while matched < ECHO_EXIT.len() {
+        let Some(byte) = uart.read_clean_byte() else {
+            continue;
+        };
+        uart.write_byte(byte);
+        matched = match byte {
+            b if b == ECHO_EXIT[matched] => matched + 1,
+            b if b == ECHO_EXIT[0] => 1,
+            _ => 0,
+        };
+    }
+    writeln!(uart, "\r\necho mode left\r").ok();
+}
+
+impl core::fmt::Write for BootSerial {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! Read-only inspection of the application slots
+//!
+//! Slots are accessed straight through the flash memory map, nothing here
+//! writes to flash. The checks themselves live in `bootloader_core`.
+
+use boot_protocol::{BootTarget, SlotHeader};
+use core::ptr::read_volatile;
+
+pub use bootloader_core::header_len_ok;
+
+// Value of erased flash
+const ERASED: u32 = 0xFFFF_FFFF;
+
+fn read_word(addr: u32) -> u32 {
+    unsafe { read_volatile(addr as *const u32) }
+}
+
+/// The whole slot as seen through the flash memory map
+pub fn slot_bytes(target: BootTarget) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(target.addr() as *const u8, target.size() as usize) }
+}
+
+/// Whether the slot looks freshly erased (no vector table at all)
+pub fn is_erased(target: BootTarget) -> bool {
+    let base = target.addr();
+    read_word(base) == ERASED && read_word(base + 4) == ERASED
+}
+
+/// Sanity check of the slot's vector table, see `BootTarget::has_valid_vectors`
+pub fn is_valid_app(target: BootTarget) -> bool {
+    target.has_valid_vectors()
+}
+
+/// Reads the slot's image header, `None` if there isn't one
+pub fn read_header(target: BootTarget) -> Option<SlotHeader> {
+    bootloader_core::parse_header(slot_bytes(target))
+}
+
+/// The image described by `header` as two slices around the header itself,
+/// see `bootloader_core::image_parts`
+pub fn image_parts(target: BootTarget, header: &SlotHeader) -> (&'static [u8], &'static [u8]) {
+    bootloader_core::image_parts(slot_bytes(target), header)
+}
+
+/// CRC-32 of the image described by `header`, header bytes excluded
+pub fn image_crc(target: BootTarget, header: &SlotHeader) -> u32 {
+    bootloader_core::image_crc(slot_bytes(target), header)
+}
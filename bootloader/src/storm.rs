@@ -0,0 +1,94 @@
+//! Switch storm guard (`switch-guard` feature)
+//!
+//! Two apps that keep requesting each other reset the chip over and over
+//! without ever crashing. Every switch request is timestamped with the RTC
+//! and counted in a window kept in the backup registers; once more than
+//! [`MAX_SWITCHES`] land within [`WINDOW_S`] seconds, requests are ignored
+//! and the default slot is booted until the window runs out.
+//!
+//! The RTC runs off the LSI. Every reset switches the LSI off, so the RTC
+//! pauses briefly until the bootloader turns it back on, which only makes
+//! the window slightly longer. Left running, the RTC stays on LSI for good:
+//! its clock source only changes after a backup domain reset.
+
+use core::ptr::{read_volatile, write_volatile};
+use stm32f4::stm32f411 as pac;
+
+/// Switches tolerated per window
+pub const MAX_SWITCHES: u32 = 5;
+
+/// Length of the window, in RTC seconds (LSI: roughly seconds)
+pub const WINDOW_S: u32 = 10;
+
+// Window start and switch count, past BKP0R (the magic with rtc-backup)
+const BKP_WINDOW_START: *mut u32 = 0x4000_2854 as *mut u32; // RTC_BKP1R
+const BKP_SWITCHES: *mut u32 = 0x4000_2858 as *mut u32; // RTC_BKP2R
+
+// RCC_BDCR fields
+const BDCR_RTCSEL_LSI: u32 = 0b10 << 8;
+const BDCR_RTCEN: u32 = 1 << 15;
+
+// RTC_ISR registers-synchronised flag
+const ISR_RSF: u32 = 1 << 5;
+
+/// Starts the RTC if needed and returns a running seconds count
+///
+/// Day of month plus time of day: it jumps at the end of a month, which at
+/// worst restarts a window early.
+fn rtc_seconds() -> u32 {
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let rtc = unsafe { &*pac::RTC::ptr() };
+
+    rcc.csr().modify(|_, w| w.lsion().set_bit());
+    while rcc.csr().read().lsirdy().bit_is_clear() {}
+    if rcc.bdcr().read().bits() & BDCR_RTCEN == 0 {
+        rcc.bdcr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | BDCR_RTCSEL_LSI | BDCR_RTCEN) });
+    }
+
+    // Shadow registers are stale after a reset: wait for a fresh copy
+    rtc.wpr().write(|w| unsafe { w.bits(0xCA) });
+    rtc.wpr().write(|w| unsafe { w.bits(0x53) });
+    rtc.isr()
+        .modify(|r, w| unsafe { w.bits(r.bits() & !ISR_RSF) });
+    while rtc.isr().read().bits() & ISR_RSF == 0 {}
+    rtc.wpr().write(|w| unsafe { w.bits(0xFF) });
+
+    // TR must be read before DR, which unlocks the shadow copy again
+    let tr = rtc.tr().read().bits();
+    let dr = rtc.dr().read().bits();
+
+    let bcd = |value: u32, shift: u32, tens_bits: u32| {
+        let units = (value >> shift) & 0xF;
+        let tens = (value >> (shift + 4)) & ((1 << tens_bits) - 1);
+        tens * 10 + units
+    };
+    let day = bcd(dr, 0, 2);
+    let hours = bcd(tr, 16, 2);
+    let minutes = bcd(tr, 8, 3);
+    let seconds = bcd(tr, 0, 3);
+    ((day * 24 + hours) * 60 + minutes) * 60 + seconds
+}
+
+/// Records a switch request and reports whether switching is storming
+///
+/// Needs backup domain write access (`boot_protocol::enable_backup_access`).
+pub fn detect_switch_storm() -> bool {
+    let now = rtc_seconds();
+    let (start, switches) =
+        unsafe { (read_volatile(BKP_WINDOW_START), read_volatile(BKP_SWITCHES)) };
+
+    // A clock that went backwards (month rollover) also starts a new window
+    let elapsed = now.wrapping_sub(start);
+    let (start, switches) = if elapsed > WINDOW_S {
+        (now, 1)
+    } else {
+        (start, switches + 1)
+    };
+
+    unsafe {
+        write_volatile(BKP_WINDOW_START, start);
+        write_volatile(BKP_SWITCHES, switches);
+    }
+    switches > MAX_SWITCHES
+}
@@ -0,0 +1,128 @@
+//! Boot slot selected by strap pins at power-on (`boot-strap` feature)
+//!
+//! Each pin in [`STRAP_PINS`] is one bit of a value, the first pin bit 0. A
+//! pin reads as 1 when strapped to ground (they are sampled with the pull-up
+//! on), so an unstrapped board reads 0. At power-on a non-zero value picks
+//! the slot booted without a request, in place of the persisted default:
+//! value 1 selects slot index 0 (App1), 2 index 1 and so on, clamped to the
+//! last slot there is. With four pins an expander board can address up to 15
+//! slots; this bootloader has two, so everything from 2 up boots App2.
+//!
+//! The pins go back to their reset state (input, no pull, port clock as it
+//! was) before the app is entered.
+
+use crate::delay_ms;
+use boot_protocol::BootTarget;
+use core::ptr::{read_volatile, write_volatile};
+use stm32f4::stm32f411 as pac;
+
+/// Pull-ups to settle, and the gap between the samples that have to agree
+const SETTLE_MS: u32 = 1;
+
+/// Sample pairs tried before a strap that keeps changing is given up on
+const TRIES: u32 = 10;
+
+/// One strap input: GPIO port (`'A'`..`'E'`, `'H'`) and pin number
+#[derive(Clone, Copy)]
+pub struct StrapPin {
+    pub port: char,
+    pub pin: u8,
+}
+
+impl StrapPin {
+    pub const fn new(port: char, pin: u8) -> Self {
+        Self { port, pin }
+    }
+
+    /// Index of the port, which is both its AHB1ENR bit and its register
+    /// block number
+    const fn port_index(self) -> u32 {
+        match self.port {
+            'A'..='E' => self.port as u32 - 'A' as u32,
+            'H' => 7,
+            _ => panic!("no such GPIO port"),
+        }
+    }
+
+    fn base(self) -> u32 {
+        0x4002_0000 + 0x400 * self.port_index()
+    }
+}
+
+/// The board's strap pins, bit 0 first: PB12..PB15, free on the Blackpill
+pub const STRAP_PINS: [StrapPin; 4] = [
+    StrapPin::new('B', 12),
+    StrapPin::new('B', 13),
+    StrapPin::new('B', 14),
+    StrapPin::new('B', 15),
+];
+
+// GPIO register offsets
+const MODER: u32 = 0x00;
+const PUPDR: u32 = 0x0C;
+const IDR: u32 = 0x10;
+
+const PUPDR_PULL_UP: u32 = 0b01;
+
+fn modify(addr: u32, f: impl FnOnce(u32) -> u32) {
+    unsafe { write_volatile(addr as *mut u32, f(read_volatile(addr as *const u32))) };
+}
+
+fn sample(pins: &[StrapPin]) -> u16 {
+    pins.iter().enumerate().fold(0, |value, (bit, pin)| {
+        let idr = unsafe { read_volatile((pin.base() + IDR) as *const u32) };
+        let strapped = idr & (1 << pin.pin) == 0;
+        value | (u16::from(strapped) << bit)
+    })
+}
+
+/// The value strapped on `pins` (at most 16), 0 if it didn't hold still
+///
+/// Two samples [`SETTLE_MS`] apart have to agree, so a jumper being moved or
+/// a slow pull-up isn't read halfway.
+pub fn read_strap(pins: &[StrapPin]) -> u16 {
+    assert!(pins.len() <= 16, "a strap value has 16 bits");
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let clocks = rcc.ahb1enr().read().bits();
+
+    for pin in pins {
+        rcc.ahb1enr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << pin.port_index())) });
+        let shift = 2 * pin.pin as u32;
+        modify(pin.base() + MODER, |r| r & !(0b11 << shift));
+        modify(pin.base() + PUPDR, |r| {
+            (r & !(0b11 << shift)) | (PUPDR_PULL_UP << shift)
+        });
+    }
+
+    delay_ms(SETTLE_MS);
+    let mut value = None;
+    let mut last = sample(pins);
+    for _ in 0..TRIES {
+        delay_ms(SETTLE_MS);
+        let next = sample(pins);
+        if next == last {
+            value = Some(next);
+            break;
+        }
+        last = next;
+    }
+
+    // Back to the reset state: MODER is left at input, the pull comes off
+    for pin in pins {
+        modify(pin.base() + PUPDR, |r| r & !(0b11 << (2 * pin.pin as u32)));
+    }
+    rcc.ahb1enr().write(|w| unsafe { w.bits(clocks) });
+
+    value.unwrap_or_else(|| {
+        warn!("strap pins keep changing, ignoring them");
+        0
+    })
+}
+
+/// The slot a strap value selects, `None` for 0 (nothing strapped)
+pub fn strap_slot(value: u16) -> Option<BootTarget> {
+    let index = usize::from(value.checked_sub(1)?);
+    let all = BootTarget::ALL;
+    Some(all[index.min(all.len() - 1)])
+}
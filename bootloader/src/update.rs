@@ -0,0 +1,477 @@
+//! Update mode: the bootloader stays resident and takes commands on USART2
+//!
+//! Entered when an app stores `MAGIC_UPDATE` and resets (see
+//! `BootRequest::UpdateMode`). Commands are plain text lines ended by CR or
+//! LF, so any serial terminal can drive it:
+//!
+//! | Command  | Effect                                      |
+//! |----------|---------------------------------------------|
+//! | `help`   | Lists the commands                          |
+//! | `info`   | Bootloader version, protocol and features   |
+//! | `status` | Shows whether each slot holds a bootable app |
+//! | `delta N`| Rebuilds slot N from the other slot and a patch, see below |
+//! | `dump N OFF LEN [hex]` | Streams `LEN` bytes of slot N from `OFF` |
+//! | `ymodem` | Receives a YMODEM batch, `app1.bin`/`app2.bin` go to their slot |
+//! | `erase N`| Erases slot N after a `y` to confirm (not the recovery slot) |
+//! | `echo`   | Echoes every byte back until `+++`, to check the wiring |
+//! | `default [N]` | Shows the slot booted without a request, or sets it to N |
+//! | `swap`   | Exchanges the App1/App2 roles, echoes the new mapping (`auth` first with `secure-boot`) |
+//! | `boot`   | Leaves update mode through a normal boot    |
+//! | `auth [RESP]` | Prints a challenge, or answers it (`secure-boot`) |
+//!
+//! With `secure-boot` the commands that write flash (`delta`, `ymodem`,
+//! `erase`, and `swap` and `default N`, which append to the settings log) are
+//! refused until `auth` has been answered, see `crate::auth`. `default`
+//! without a slot only reads.
+//!
+//! The magic has already been cleared by the time this runs, so leaving (or
+//! power cycling out of a failed update) lands in the default boot path.
+//!
+//! Binary transfers (the `delta` patch, see `crate::delta`) are paced by the
+//! bootloader, since the UART has no FIFO and flash programming stalls the
+//! core: it sends ACK (0x06) whenever it is ready for the next
+//! [`CHUNK`]-byte chunk, and the host sends one chunk per ACK, zero-padding
+//! the last one.
+
+#[cfg(feature = "secure-boot")]
+use crate::auth;
+use crate::bor;
+use crate::delta::{self, PatchStream};
+use crate::flash::{self, FlashError};
+use crate::info::bootloader_info;
+use crate::persist;
+use crate::serial::{self, BootSerial};
+use crate::slot;
+use crate::xmodem;
+use crate::ymodem;
+use boot_protocol::{unpack_version, BootTarget, SlotRoles, BOOTLOADER_ADDR, BOOTLOADER_SIZE};
+use bootloader_core::RECOVERY_SLOT;
+use core::fmt::Write;
+
+// Longest command line accepted, anything longer is discarded. Room for an
+// `auth` response, 5 + 128 hex digits
+const LINE_MAX: usize = 140;
+
+/// Bytes the host may send per ACK during a binary transfer
+pub const CHUNK: usize = 128;
+
+const ACK: u8 = 0x06;
+
+/// Binary transfer over the console, one ACKed chunk at a time
+struct ChunkedStream<'a> {
+    serial: &'a mut BootSerial,
+    buf: [u8; CHUNK],
+    pos: usize,
+}
+
+impl<'a> ChunkedStream<'a> {
+    fn new(serial: &'a mut BootSerial) -> Self {
+        Self {
+            serial,
+            buf: [0; CHUNK],
+            pos: CHUNK,
+        }
+    }
+}
+
+impl PatchStream for ChunkedStream<'_> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), FlashError> {
+        for byte in buf {
+            if self.pos == CHUNK {
+                // Whole chunk is received before anything else runs, so
+                // nothing arrives while flash is being programmed
+                self.serial.write_byte(ACK);
+                for slot in self.buf.iter_mut() {
+                    *slot = self.serial.read_byte();
+                }
+                self.pos = 0;
+            }
+            *byte = self.buf[self.pos];
+            self.pos += 1;
+        }
+        Ok(())
+    }
+}
+
+/// What update mode keeps between commands
+#[derive(Default)]
+struct State {
+    #[cfg(feature = "secure-boot")]
+    session: auth::Session,
+}
+
+/// Runs the command loop until the host asks to boot
+pub fn run() -> ! {
+    let mut serial = BootSerial::init();
+    #[cfg(feature = "secure-boot")]
+    auth::init();
+    let mut state = State::default();
+    writeln!(serial, "\r\n=== BOOTLOADER UPDATE MODE ===\r").ok();
+    writeln!(serial, "type 'help' for commands\r").ok();
+
+    let mut line = [0u8; LINE_MAX];
+    loop {
+        write!(serial, "> ").ok();
+        match read_line(&mut serial, &mut line) {
+            Some(len) => execute(&mut serial, &mut state, &line[..len]),
+            None => {
+                writeln!(serial, "line too long\r").ok();
+            }
+        }
+    }
+}
+
+/// Reads one line into `buf`, `None` if it didn't fit
+fn read_line(serial: &mut BootSerial, buf: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut overflow = false;
+    loop {
+        match serial.read_byte() {
+            b'\r' | b'\n' if len == 0 && !overflow => continue, // CRLF, blank line
+            b'\r' | b'\n' => return (!overflow).then_some(len),
+            byte if len < buf.len() => {
+                buf[len] = byte;
+                len += 1;
+            }
+            _ => overflow = true,
+        }
+    }
+}
+
+/// `b"1"`/`b"2"` to the matching slot
+fn parse_slot(arg: &[u8]) -> Option<BootTarget> {
+    match arg {
+        b"1" => Some(BootTarget::App1),
+        b"2" => Some(BootTarget::App2),
+        _ => None,
+    }
+}
+
+/// `1234` or `0x4d2`
+fn parse_u32(arg: &[u8]) -> Option<u32> {
+    let (digits, radix) = match arg {
+        [b'0', b'x' | b'X', hex @ ..] => (hex, 16),
+        _ => (arg, 10),
+    };
+    u32::from_str_radix(core::str::from_utf8(digits).ok()?, radix).ok()
+}
+
+/// `2*N` hex digits to their bytes
+#[cfg(feature = "secure-boot")]
+fn parse_hex<const N: usize>(arg: &[u8]) -> Option<[u8; N]> {
+    if arg.len() != 2 * N {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (byte, digits) in bytes.iter_mut().zip(arg.chunks_exact(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+// Flash regions that may be read back: the bootloader and both slots
+const DUMP_REGIONS: [(u32, u32); 3] = [
+    (BOOTLOADER_ADDR, BOOTLOADER_SIZE),
+    (BootTarget::App1.addr(), BootTarget::App1.size()),
+    (BootTarget::App2.addr(), BootTarget::App2.size()),
+];
+
+/// Streams `len` bytes of flash from `addr` to `uart`, raw or as hex
+///
+/// The range has to lie entirely within the bootloader or one slot, and
+/// nothing is sent while readout protection is on.
+pub fn dump_region(
+    uart: &mut BootSerial,
+    addr: u32,
+    len: u32,
+    hex: bool,
+) -> Result<(), FlashError> {
+    let in_region = DUMP_REGIONS
+        .iter()
+        .any(|&(start, size)| bootloader_core::range_within(addr, len, start, size));
+    if !in_region {
+        return Err(FlashError::OutOfBounds);
+    }
+    if flash::is_read_protected() {
+        return Err(FlashError::ReadProtected);
+    }
+
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) };
+    if hex {
+        for line in bytes.chunks(16) {
+            for byte in line {
+                write!(uart, "{:02x}", byte).ok();
+            }
+            writeln!(uart, "\r").ok();
+        }
+    } else {
+        for &byte in bytes {
+            uart.write_byte(byte);
+        }
+    }
+    Ok(())
+}
+
+/// Handles `dump N OFF LEN [hex]`
+fn dump_command(serial: &mut BootSerial, args: &[u8]) {
+    let mut args = args.split(|&b| b == b' ').filter(|arg| !arg.is_empty());
+    let target = args.next().and_then(parse_slot);
+    let offset = args.next().and_then(parse_u32);
+    let len = args.next().and_then(parse_u32);
+    let hex = match args.next() {
+        None => Some(false),
+        Some(b"hex") => Some(true),
+        Some(_) => None,
+    };
+
+    let (Some(target), Some(offset), Some(len), Some(hex)) = (target, offset, len, hex) else {
+        writeln!(serial, "usage: dump 1|2 OFFSET LEN [hex]\r").ok();
+        return;
+    };
+    // An offset past the slot is caught by the region check
+    let addr = target.addr().saturating_add(offset);
+    if let Err(e) = dump_region(serial, addr, len, hex) {
+        writeln!(serial, "dump failed: {}\r", e).ok();
+    }
+}
+
+/// Erases `target` and rebuilds it from the other slot plus a patch
+fn delta_update(serial: &mut BootSerial, target: BootTarget) {
+    let base = match target {
+        BootTarget::App1 => BootTarget::App2,
+        BootTarget::App2 => BootTarget::App1,
+    };
+
+    writeln!(serial, "erasing {:?}\r", target).ok();
+    let result = flash::erase_slot(target)
+        .and_then(|()| delta::apply_patch(base, target, &mut ChunkedStream::new(serial)));
+    match result {
+        Ok(len) => writeln!(serial, "\r\ndelta ok, {} bytes\r", len).ok(),
+        Err(e) => writeln!(serial, "\r\ndelta failed: {}\r", e).ok(),
+    };
+}
+
+/// Erases `target` once the operator confirms with `y`
+///
+/// The slot playing the recovery role is refused: it is what a unit falls
+/// back to.
+fn erase_command(serial: &mut BootSerial, target: BootTarget) {
+    if target == SlotRoles::load().slot(RECOVERY_SLOT) {
+        writeln!(
+            serial,
+            "{:?} is the recovery slot, refusing to erase it\r",
+            target
+        )
+        .ok();
+        return;
+    }
+
+    write!(serial, "erase {:?}? press 'y' to confirm: ", target).ok();
+    let confirmed = serial.read_byte() == b'y';
+    writeln!(serial, "\r").ok();
+    if !confirmed {
+        writeln!(serial, "cancelled\r").ok();
+        return;
+    }
+
+    match flash::erase_slot(target) {
+        Ok(()) if slot::is_valid_app(target) => {
+            writeln!(serial, "erase failed to clear the slot\r").ok()
+        }
+        Ok(()) => writeln!(serial, "{:?} erased, it won't be booted\r", target).ok(),
+        Err(e) => writeln!(serial, "erase failed: {}\r", e).ok(),
+    };
+}
+
+/// `default` shows the persisted default slot, `default N` sets it
+///
+/// The new default is read back and echoed. It applies from the next boot that
+/// no app asked for, the current one (update mode) is unaffected. Setting it
+/// writes the settings log (see `crate::persist`), which a failure is
+/// reported for.
+fn default_command(serial: &mut BootSerial, arg: &[u8]) {
+    if !arg.is_empty() {
+        let Some(target) = parse_slot(arg) else {
+            writeln!(serial, "usage: default [1|2]\r").ok();
+            return;
+        };
+        if let Err(e) = persist::persist_slot(target) {
+            writeln!(serial, "not saved to flash, lost without VBAT: {}\r", e).ok();
+        }
+    }
+
+    match persist::load_persisted_slot() {
+        Some(target) if !arg.is_empty() => {
+            writeln!(serial, "default is now {:?}, from the next boot\r", target).ok()
+        }
+        Some(target) => writeln!(serial, "default: {:?}\r", target).ok(),
+        None => {
+            let recovery = SlotRoles::load().slot(RECOVERY_SLOT);
+            writeln!(serial, "default: unset ({:?})\r", recovery).ok()
+        }
+    };
+}
+
+/// `swap` exchanges the slots' roles, see `boot_protocol::SlotRoles`
+///
+/// The new mapping is read back and echoed. A default set with `default N`
+/// names a slot, not a role, so it keeps applying until changed. Like
+/// `default N` it writes the settings log.
+fn swap_command(serial: &mut BootSerial) {
+    let (roles, stored) = persist::swap_slot_roles();
+    if let Err(e) = stored {
+        writeln!(serial, "not saved to flash, lost without VBAT: {}\r", e).ok();
+    }
+    for role in BootTarget::ALL {
+        writeln!(
+            serial,
+            "{:?} role is now slot {:?}, from the next boot\r",
+            role,
+            roles.slot(role)
+        )
+        .ok();
+    }
+    if let Some(target) = persist::load_persisted_slot() {
+        writeln!(serial, "default {:?} still applies\r", target).ok();
+    }
+}
+
+/// `auth` prints a new challenge, `auth RESP` answers the pending one
+#[cfg(feature = "secure-boot")]
+fn auth_command(serial: &mut BootSerial, session: &mut auth::Session, arg: &[u8]) {
+    if arg.is_empty() {
+        let challenge = session.challenge();
+        if challenge.source() == auth::Entropy::CycleCounter {
+            writeln!(
+                serial,
+                "warning: no RNG, challenge seeded from the cycle counter\r"
+            )
+            .ok();
+        }
+        write!(serial, "challenge ").ok();
+        for byte in challenge.bytes() {
+            write!(serial, "{:02x}", byte).ok();
+        }
+        writeln!(serial, "\r").ok();
+        return;
+    }
+
+    let Some(response) = parse_hex(arg) else {
+        writeln!(serial, "usage: auth [SIGNATURE as 128 hex digits]\r").ok();
+        return;
+    };
+    if session.respond(&response) {
+        writeln!(serial, "authenticated, flash writes enabled\r").ok();
+    } else {
+        writeln!(serial, "authentication failed, ask for a new challenge\r").ok();
+    }
+}
+
+/// Whether commands may write flash, telling the operator if not
+#[cfg_attr(not(feature = "secure-boot"), allow(unused_variables))]
+fn writes_allowed(serial: &mut BootSerial, state: &State) -> bool {
+    #[cfg(feature = "secure-boot")]
+    if !state.session.is_authed() {
+        writeln!(serial, "not authenticated, run 'auth' first\r").ok();
+        return false;
+    }
+    true
+}
+
+fn execute(serial: &mut BootSerial, state: &mut State, line: &[u8]) {
+    let line = line.trim_ascii();
+    let (command, arg) = match line.iter().position(|&b| b == b' ') {
+        Some(space) => (&line[..space], line[space + 1..].trim_ascii()),
+        None => (line, &[][..]),
+    };
+
+    match command {
+        b"help" => {
+            writeln!(serial, "help   - this list\r").ok();
+            writeln!(serial, "info   - bootloader version\r").ok();
+            writeln!(serial, "status - slot summary\r").ok();
+            writeln!(serial, "delta N - patch slot N (1|2) from the other slot\r").ok();
+            writeln!(serial, "dump N OFF LEN [hex] - read back slot N\r").ok();
+            writeln!(serial, "ymodem - receive app1.bin/app2.bin in one batch\r").ok();
+            writeln!(serial, "erase N - erase slot N (asks to confirm)\r").ok();
+            writeln!(serial, "default [N] - show/set the default slot\r").ok();
+            writeln!(serial, "swap   - exchange the App1/App2 roles\r").ok();
+            writeln!(serial, "echo   - echo bytes back until +++\r").ok();
+            writeln!(serial, "boot   - leave update mode\r").ok();
+            #[cfg(feature = "secure-boot")]
+            writeln!(
+                serial,
+                "auth [RESP] - get/answer a challenge, for flash writes\r"
+            )
+            .ok();
+        }
+        b"info" => {
+            let info = bootloader_info();
+            let (major, minor, patch) = unpack_version(info.version);
+            writeln!(serial, "bootloader v{}.{}.{}\r", major, minor, patch).ok();
+            writeln!(serial, "protocol {}\r", info.protocol_version).ok();
+            writeln!(serial, "max image {} bytes\r", info.max_image_size).ok();
+            writeln!(serial, "features {:#010x}\r", info.features).ok();
+            writeln!(serial, "BOR {}\r", bor::current_level()).ok();
+        }
+        b"status" => {
+            let roles = SlotRoles::load();
+            for target in [BootTarget::App1, BootTarget::App2] {
+                let state = if slot::is_erased(target) {
+                    "empty"
+                } else if slot::is_valid_app(target) {
+                    "valid"
+                } else {
+                    "invalid"
+                };
+                writeln!(
+                    serial,
+                    "{:?} @ {:#010x}: {}, {:?} role\r",
+                    target,
+                    target.addr(),
+                    state,
+                    roles.role(target)
+                )
+                .ok();
+            }
+        }
+        b"delta" | b"ymodem" | b"erase" | b"swap" if !writes_allowed(serial, state) => {}
+        b"default" if !arg.is_empty() && !writes_allowed(serial, state) => {}
+        b"delta" => match parse_slot(arg) {
+            Some(target) => delta_update(serial, target),
+            None => {
+                writeln!(serial, "usage: delta 1|2\r").ok();
+            }
+        },
+        b"dump" => dump_command(serial, arg),
+        b"erase" => match parse_slot(arg) {
+            Some(target) => erase_command(serial, target),
+            None => {
+                writeln!(serial, "usage: erase 1|2\r").ok();
+            }
+        },
+        b"default" => default_command(serial, arg),
+        b"swap" => swap_command(serial),
+        b"echo" => serial::uart_echo_loop(serial),
+        b"ymodem" => {
+            writeln!(serial, "start the YMODEM batch now\r").ok();
+            let result = ymodem::receive_batch(serial);
+            // Let the sender wind down before talking again
+            xmodem::purge(serial);
+            match result {
+                Ok(files) => writeln!(serial, "\r\nymodem ok, {} files\r", files).ok(),
+                Err(e) => writeln!(serial, "\r\nymodem failed: {}\r", e).ok(),
+            };
+        }
+        #[cfg(feature = "secure-boot")]
+        b"auth" => auth_command(serial, &mut state.session, arg),
+        b"boot" => {
+            writeln!(serial, "booting\r").ok();
+            serial.flush();
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+        _ => {
+            writeln!(serial, "unknown command, try 'help'\r").ok();
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Verified-image cache (`verify-cache` feature)
+//!
+//! Full verification (image CRC against the header, plus the signature with
+//! `secure-boot`) reads the whole slot on every boot. Once a slot has passed
+//! it, its header CRC is remembered in a backup register; later boots only
+//! compare the header against that record and skip the full check while
+//! they match. Any mismatch, an unstamped header, or a lost record (backup
+//! domain reset) goes back to full verification. Erasing a slot, which every
+//! update does first, drops its record.
+//!
+//! Threat model: the quick check trusts that nothing rewrites a slot behind
+//! the bootloader's back. An image changed over SWD, or by an app writing
+//! flash itself, while keeping the old header boots unverified until the
+//! record is lost. Builds that must never run an unverified image (e.g.
+//! secure boot against physical attackers) should leave this feature off.
+
+use crate::slot;
+use boot_protocol::BootTarget;
+use core::ptr::{read_volatile, write_volatile};
+
+// Per-slot records, past the storm guard's BKP1R/BKP2R
+const fn record(target: BootTarget) -> *mut u32 {
+    match target {
+        BootTarget::App1 => 0x4000_285C as *mut u32, // RTC_BKP3R
+        BootTarget::App2 => 0x4000_2860 as *mut u32, // RTC_BKP4R
+    }
+}
+
+// A record that can't match any header: nothing verified
+const NONE: u32 = 0;
+
+/// Whether the slot has to be fully verified before booting it
+///
+/// `false` only when its stamped header's CRC is the one recorded after the
+/// last full verification of this slot.
+pub fn needs_full_verify(target: BootTarget) -> bool {
+    let Some(header) = slot::read_header(target) else {
+        return true;
+    };
+    let recorded = unsafe { read_volatile(record(target)) };
+    !(header.is_stamped() && recorded != NONE && recorded == header.crc32)
+}
+
+/// Remembers that the slot's current image passed full verification
+pub fn record_verified(target: BootTarget) {
+    let crc = match slot::read_header(target) {
+        Some(header) if header.is_stamped() => header.crc32,
+        _ => NONE,
+    };
+    write_record(target, crc);
+}
+
+/// Forgets the slot's record, its contents are about to change
+pub fn invalidate(target: BootTarget) {
+    write_record(target, NONE);
+}
+
+fn write_record(target: BootTarget, crc: u32) {
+    unsafe {
+        boot_protocol::enable_backup_access();
+        write_volatile(record(target), crc);
+    }
+}
+
+/// Full check of the image against its stamped header: length and CRC
+///
+/// Unstamped images have nothing to check against and pass.
+pub fn image_crc_ok(target: BootTarget) -> bool {
+    match slot::read_header(target) {
+        Some(header) if header.is_stamped() => {
+            slot::header_len_ok(target, &header) && slot::image_crc(target, &header) == header.crc32
+        }
+        _ => true,
+    }
+}
@@ -0,0 +1,100 @@
+//! Self-test of the vector table relocation the jump relies on
+//!
+//! `jump` hands over by pointing VTOR at the app's table. Should that write
+//! not take, the app's interrupts would dispatch through the bootloader's
+//! table instead, and nothing but the interrupts would notice. The check
+//! builds a stand-in for an app's table in RAM, whose vector for
+//! `PROBE_IRQ` is the probe handler, points VTOR at it and pends the
+//! interrupt. The bootloader's own table has a handler for the same
+//! interrupt too, so a dispatch through the wrong table is seen as such
+//! instead of ending up in the default handler's endless loop.
+
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicU8, Ordering};
+use cortex_m::peripheral::{NVIC, SCB};
+use stm32f4::stm32f411 as pac;
+use stm32f4::stm32f411::interrupt;
+
+/// Spare interrupt used for the probe, the bootloader doesn't use EXTI
+const PROBE_IRQ: pac::Interrupt = pac::Interrupt::EXTI1;
+
+// Which table the probe was dispatched through
+const NOT_TAKEN: u8 = 0;
+const RELOCATED: u8 = 1;
+const BOOTLOADER: u8 = 2;
+
+static DISPATCHED: AtomicU8 = AtomicU8::new(NOT_TAKEN);
+
+/// Exceptions plus the F411's 86 interrupts, rounded up to the table's
+/// alignment (VTOR needs a power of two at least the table's size)
+const TABLE_WORDS: usize = 128;
+
+#[repr(C, align(512))]
+struct VectorTable([u32; TABLE_WORDS]);
+
+static mut PROBE_TABLE: VectorTable = VectorTable([0; TABLE_WORDS]);
+
+/// The probe vector of the RAM table
+extern "C" fn probe_handler() {
+    NVIC::unpend(PROBE_IRQ);
+    DISPATCHED.store(RELOCATED, Ordering::SeqCst);
+}
+
+/// The same interrupt through the bootloader's own table: VTOR didn't move
+#[interrupt]
+fn EXTI1() {
+    NVIC::unpend(PROBE_IRQ);
+    DISPATCHED.store(BOOTLOADER, Ordering::SeqCst);
+}
+
+/// Whether `PROBE_IRQ`, pended with VTOR at `base`, runs the handler in
+/// `base`'s table rather than the bootloader's
+///
+/// `base` must hold a vector table whose `PROBE_IRQ` vector is the probe
+/// handler, such as the one [`relocation_works`] sets up. VTOR is put back
+/// afterwards and the interrupt is left masked.
+pub fn verify_vtor_dispatch(base: u32) -> bool {
+    let scb = unsafe { &*SCB::PTR };
+    let saved = scb.vtor.read();
+    let primask = cortex_m::register::primask::read();
+
+    DISPATCHED.store(NOT_TAKEN, Ordering::SeqCst);
+    unsafe { scb.vtor.write(base) };
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    NVIC::unpend(PROBE_IRQ);
+    unsafe {
+        NVIC::unmask(PROBE_IRQ);
+        cortex_m::interrupt::enable();
+    }
+    NVIC::pend(PROBE_IRQ);
+    // Taken as soon as the pend has landed
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    // Only put interrupts back off if they were off before
+    if primask.is_inactive() {
+        cortex_m::interrupt::disable();
+    }
+    NVIC::mask(PROBE_IRQ);
+    NVIC::unpend(PROBE_IRQ);
+    unsafe { scb.vtor.write(saved) };
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    DISPATCHED.load(Ordering::SeqCst) == RELOCATED
+}
+
+/// Sets up the RAM table (the bootloader's own, with the probe vector
+/// swapped in) and checks that dispatch follows VTOR to it
+pub fn relocation_works() -> bool {
+    let table = unsafe { &mut (*addr_of_mut!(PROBE_TABLE)).0 };
+    let own = unsafe { (*SCB::PTR).vtor.read() } as *const u32;
+    for (index, vector) in table.iter_mut().enumerate() {
+        *vector = unsafe { own.add(index).read_volatile() };
+    }
+    table[16 + PROBE_IRQ as usize] = probe_handler as usize as u32;
+
+    verify_vtor_dispatch(table.as_ptr() as u32)
+}
@@ -0,0 +1,129 @@
+//! XMODEM-CRC / XMODEM-1K block layer, receiver side
+//!
+//! A block is `SOH|STX, seq, !seq, data[128|1024], CRC-16 (big-endian)`.
+//! [`receive_packet`] retries bad or missing blocks by itself, callers only
+//! see good blocks, EOT, or a failed session. The sender waits for our ACK
+//! after each block, so a block is always complete in RAM before any flash
+//! is programmed.
+
+use crate::flash::FlashError;
+use crate::serial::BootSerial;
+use bootloader_core::crc16_xmodem;
+use core::fmt;
+
+pub const SOH: u8 = 0x01;
+pub const STX: u8 = 0x02;
+pub const EOT: u8 = 0x04;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const CAN: u8 = 0x18;
+/// Sent instead of NAK to ask for CRC-16 blocks
+pub const CRC_MODE: u8 = b'C';
+
+/// Largest block data, XMODEM-1K
+pub const BLOCK_MAX: usize = 1024;
+
+// How long to wait for the sender before polling again, and how often
+const TIMEOUT_MS: u32 = 1000;
+const MAX_RETRIES: usize = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum XmodemError {
+    /// No good block after `MAX_RETRIES` attempts
+    Timeout,
+    /// The sender cancelled the session (CAN)
+    Cancelled,
+    /// A block arrived out of order
+    Sequence,
+    /// A YMODEM header didn't parse or had an impossible size
+    BadHeader,
+    /// A YMODEM file name doesn't name a slot
+    UnknownFile,
+    /// Erasing or programming the slot failed
+    Flash(FlashError),
+}
+
+impl From<FlashError> for XmodemError {
+    fn from(err: FlashError) -> Self {
+        XmodemError::Flash(err)
+    }
+}
+
+impl fmt::Display for XmodemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            XmodemError::Timeout => f.write_str("timed out waiting for the sender"),
+            XmodemError::Cancelled => f.write_str("cancelled by the sender"),
+            XmodemError::Sequence => f.write_str("block out of sequence"),
+            XmodemError::BadHeader => f.write_str("malformed file header"),
+            XmodemError::UnknownFile => f.write_str("file name doesn't name a slot"),
+            XmodemError::Flash(err) => write!(f, "flash: {}", err),
+        }
+    }
+}
+
+/// What the sender sent
+pub enum Packet {
+    /// A good block, its data is the first `len` bytes of the buffer
+    Block { seq: u8, len: usize },
+    /// End of the current file
+    Eot,
+}
+
+/// Receives the next packet into `buf`
+///
+/// `poll` is sent whenever the line stays quiet: `CRC_MODE` while waiting
+/// for a transfer to start, `NAK` once it runs. Corrupt blocks are NAKed and
+/// received again.
+pub fn receive_packet(
+    serial: &mut BootSerial,
+    buf: &mut [u8; BLOCK_MAX],
+    poll: u8,
+) -> Result<Packet, XmodemError> {
+    for _ in 0..MAX_RETRIES {
+        let len = match serial.read_byte_timeout(TIMEOUT_MS) {
+            Some(SOH) => 128,
+            Some(STX) => BLOCK_MAX,
+            Some(EOT) => return Ok(Packet::Eot),
+            Some(CAN) => return Err(XmodemError::Cancelled),
+            // Line noise between blocks
+            Some(_) => continue,
+            None => {
+                serial.write_byte(poll);
+                continue;
+            }
+        };
+
+        if let Some(seq) = read_block(serial, &mut buf[..len]) {
+            return Ok(Packet::Block { seq, len });
+        }
+        purge(serial);
+        serial.write_byte(NAK);
+    }
+    Err(XmodemError::Timeout)
+}
+
+/// Reads the rest of a block after its start byte, `None` if it is corrupt
+fn read_block(serial: &mut BootSerial, data: &mut [u8]) -> Option<u8> {
+    let seq = serial.read_byte_timeout(TIMEOUT_MS)?;
+    let seq_inv = serial.read_byte_timeout(TIMEOUT_MS)?;
+    for byte in data.iter_mut() {
+        *byte = serial.read_byte_timeout(TIMEOUT_MS)?;
+    }
+    let crc_hi = serial.read_byte_timeout(TIMEOUT_MS)?;
+    let crc_lo = serial.read_byte_timeout(TIMEOUT_MS)?;
+
+    let crc_ok = u16::from_be_bytes([crc_hi, crc_lo]) == crc16_xmodem(data);
+    (seq == !seq_inv && crc_ok).then_some(seq)
+}
+
+/// Drops input until the line has been quiet for a while
+pub fn purge(serial: &mut BootSerial) {
+    while serial.read_byte_timeout(100).is_some() {}
+}
+
+/// Aborts the session on the sender's side
+pub fn cancel(serial: &mut BootSerial) {
+    serial.write_byte(CAN);
+    serial.write_byte(CAN);
+}
@@ -0,0 +1,123 @@
+//! YMODEM batch receiver: images for several slots in one session
+//!
+//! Every file starts with a block 0 carrying its name and size
+//! (`name NUL size ...`); the name picks the slot, see [`slot_for_name`].
+//! An empty block 0 ends the batch. Built on the XMODEM block layer in
+//! `crate::xmodem`, each file is written through `delta::SlotWriter`, so the
+//! vector table only goes in once the whole file has arrived.
+
+use crate::delta::{SlotWriter, HEAD_LEN};
+use crate::flash;
+use crate::serial::BootSerial;
+use crate::xmodem::{
+    self, receive_packet, Packet, XmodemError, ACK, BLOCK_MAX, CRC_MODE, EOT, NAK,
+};
+use boot_protocol::BootTarget;
+
+/// The slot a file is meant for: `app1.bin` or `app2.bin`
+///
+/// Anything else is refused, never routed to a default slot.
+pub fn slot_for_name(name: &[u8]) -> Option<BootTarget> {
+    match name {
+        b"app1.bin" => Some(BootTarget::App1),
+        b"app2.bin" => Some(BootTarget::App2),
+        _ => None,
+    }
+}
+
+/// Receives a whole batch, returns how many files were written
+///
+/// On any error the session is cancelled; slots completed before it keep
+/// their new image, the one in progress is left erased (unbootable).
+pub fn receive_batch(serial: &mut BootSerial) -> Result<usize, XmodemError> {
+    let mut buf = [0u8; BLOCK_MAX];
+    let mut files = 0;
+    let result = loop {
+        match receive_file(serial, &mut buf) {
+            Ok(true) => files += 1,
+            Ok(false) => break Ok(files),
+            Err(err) => break Err(err),
+        }
+    };
+    if result.is_err() {
+        xmodem::cancel(serial);
+    }
+    result
+}
+
+/// Receives one file, `false` if the sender ended the batch instead
+fn receive_file(serial: &mut BootSerial, buf: &mut [u8; BLOCK_MAX]) -> Result<bool, XmodemError> {
+    serial.write_byte(CRC_MODE);
+    let len = match receive_packet(serial, buf, CRC_MODE)? {
+        Packet::Block { seq: 0, len } => len,
+        _ => return Err(XmodemError::Sequence),
+    };
+    // Empty name: no more files
+    if buf[0] == 0 {
+        serial.write_byte(ACK);
+        return Ok(false);
+    }
+
+    let (name, size) = parse_header(&buf[..len]).ok_or(XmodemError::BadHeader)?;
+    let Some(target) = slot_for_name(name) else {
+        // Refuse the file outright, nothing has been erased
+        serial.write_byte(NAK);
+        return Err(XmodemError::UnknownFile);
+    };
+
+    // Programming is word-granular, the tail is padded with erased bytes
+    let padded = size
+        .checked_next_multiple_of(4)
+        .ok_or(XmodemError::BadHeader)?;
+    if (size as usize) < HEAD_LEN || flash::validate_image_len(target, padded).is_err() {
+        return Err(XmodemError::BadHeader);
+    }
+    flash::erase_slot(target)?;
+    // Whatever the sender repeated during the erase is stale now
+    xmodem::purge(serial);
+    serial.write_byte(ACK);
+    serial.write_byte(CRC_MODE);
+
+    let mut writer = SlotWriter::new(target, padded);
+    let mut expected: u8 = 1;
+    loop {
+        match receive_packet(serial, buf, NAK)? {
+            Packet::Block { seq, len } if seq == expected => {
+                // The last block is padded past the file size
+                let left = (size - writer.written()) as usize;
+                writer.push(&buf[..len.min(left)])?;
+                expected = expected.wrapping_add(1);
+                serial.write_byte(ACK);
+            }
+            // Our ACK got lost, the sender repeated the block
+            Packet::Block { seq, .. } if seq == expected.wrapping_sub(1) => {
+                serial.write_byte(ACK);
+            }
+            Packet::Block { .. } => return Err(XmodemError::Sequence),
+            Packet::Eot => break,
+        }
+    }
+    // YMODEM end of file: NAK the first EOT, ACK the repeated one
+    serial.write_byte(NAK);
+    if serial.read_byte_timeout(1000) != Some(EOT) {
+        return Err(XmodemError::Timeout);
+    }
+    serial.write_byte(ACK);
+
+    if writer.written() != size {
+        return Err(XmodemError::BadHeader);
+    }
+    writer.push(&[0xFF; 3][..(padded - size) as usize])?;
+    writer.flush()?;
+    writer.commit()?;
+    Ok(true)
+}
+
+/// Splits block 0 into the file name and its decimal size
+fn parse_header(block: &[u8]) -> Option<(&[u8], u32)> {
+    let name_end = block.iter().position(|&b| b == 0)?;
+    let rest = &block[name_end + 1..];
+    let size_end = rest.iter().position(|&b| b == b' ' || b == 0)?;
+    let size = core::str::from_utf8(&rest[..size_end]).ok()?.parse().ok()?;
+    Some((&block[..name_end], size))
+}
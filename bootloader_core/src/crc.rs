@@ -0,0 +1,110 @@
+//! CRC-32 (IEEE 802.3, reflected, as used by zlib) for image verification,
+//! and the CRC-16 XMODEM/YMODEM blocks carry
+//!
+//! Nibble-table implementation: 64 bytes of table instead of 1KB, which is
+//! plenty fast for checking a slot once per boot.
+
+const POLY: u32 = 0xEDB8_8320;
+
+const TABLE: [u32; 16] = make_table();
+
+const fn make_table() -> [u32; 16] {
+    let mut table = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 4 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Incremental CRC-32, for images checked in several pieces
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self { state: 0xFFFF_FFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.state;
+        for &byte in data {
+            crc ^= byte as u32;
+            crc = (crc >> 4) ^ TABLE[(crc & 0xF) as usize];
+            crc = (crc >> 4) ^ TABLE[(crc & 0xF) as usize];
+        }
+        self.state = crc;
+    }
+
+    pub const fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRC-16/XMODEM (poly 0x1021, init 0, no reflection)
+pub fn crc16_xmodem(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK: &[u8] = b"123456789";
+
+    #[test]
+    fn crc32_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(CHECK);
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn crc32_of_nothing_is_zero() {
+        assert_eq!(Crc32::new().finish(), 0);
+    }
+
+    #[test]
+    fn crc32_in_pieces_matches_one_go() {
+        let mut crc = Crc32::new();
+        for chunk in CHECK.chunks(2) {
+            crc.update(chunk);
+        }
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16_xmodem(CHECK), 0x31C3);
+        assert_eq!(crc16_xmodem(&[]), 0);
+    }
+}
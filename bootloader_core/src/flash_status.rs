@@ -0,0 +1,242 @@
+//! Waiting out a flash operation and decoding `FLASH_SR`
+//!
+//! The bootloader's flash driver starts an erase or a word write, then hands
+//! a [`FlashStatus`] view of the controller to [`wait_idle`]. Behind the
+//! trait sit the real registers on the target, or a scripted sequence of
+//! status words on the host, so the flag decoding and the timeout can be
+//! exercised without hardware.
+
+use core::fmt;
+
+// FLASH_SR bits
+pub const SR_OPERR: u32 = 1 << 1;
+pub const SR_WRPERR: u32 = 1 << 4;
+pub const SR_PGAERR: u32 = 1 << 5;
+pub const SR_PGPERR: u32 = 1 << 6;
+pub const SR_PGSERR: u32 = 1 << 7;
+pub const SR_BSY: u32 = 1 << 16;
+
+/// Every error flag, all cleared by writing 1
+pub const SR_ERRORS: u32 = SR_PGSERR | SR_PGPERR | SR_PGAERR | SR_WRPERR | SR_OPERR;
+
+/// Why a flash operation didn't complete
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlashFault {
+    /// BSY never cleared within the allowed cycles
+    Timeout,
+    /// PGSERR: programming started without the controller set up for it
+    Sequence,
+    /// PGPERR: write size doesn't match the configured parallelism
+    Parallelism,
+    /// PGAERR: write crosses a 128-bit row boundary
+    Alignment,
+    /// WRPERR: target sector is write-protected
+    WriteProtected,
+    /// OPERR: any other failed operation
+    Operation,
+}
+
+impl FlashFault {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FlashFault::Timeout => "flash controller stayed busy",
+            FlashFault::Sequence => "flash programming sequence error",
+            FlashFault::Parallelism => "flash parallelism error",
+            FlashFault::Alignment => "flash alignment error",
+            FlashFault::WriteProtected => "write to a protected sector",
+            FlashFault::Operation => "flash operation error",
+        }
+    }
+
+    /// The fault an `FLASH_SR` value reports, `None` if no error flag is set
+    ///
+    /// With several flags set the one earliest in the operation wins:
+    /// sequence, then parallelism, alignment, protection.
+    pub fn from_status(sr: u32) -> Option<Self> {
+        [
+            (SR_PGSERR, FlashFault::Sequence),
+            (SR_PGPERR, FlashFault::Parallelism),
+            (SR_PGAERR, FlashFault::Alignment),
+            (SR_WRPERR, FlashFault::WriteProtected),
+            (SR_OPERR, FlashFault::Operation),
+        ]
+        .into_iter()
+        .find(|&(flag, _)| sr & flag != 0)
+        .map(|(_, fault)| fault)
+    }
+}
+
+impl fmt::Display for FlashFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What [`wait_idle`] needs of the flash controller
+pub trait FlashStatus {
+    /// Current `FLASH_SR`
+    fn status(&mut self) -> u32;
+    /// Clears the given error flags (write 1 to clear)
+    fn clear(&mut self, flags: u32);
+    /// A free-running cycle count, wrapping
+    fn cycles(&mut self) -> u32;
+}
+
+/// Waits for BSY to clear, for at most `timeout_cycles`, then checks the
+/// error flags
+///
+/// Any error flags found are cleared before returning, so the next operation
+/// starts clean, also after a timeout.
+pub fn wait_idle<S: FlashStatus>(regs: &mut S, timeout_cycles: u32) -> Result<(), FlashFault> {
+    let start = regs.cycles();
+    let mut sr = regs.status();
+    while sr & SR_BSY != 0 {
+        if regs.cycles().wrapping_sub(start) > timeout_cycles {
+            regs.clear(sr & SR_ERRORS);
+            return Err(FlashFault::Timeout);
+        }
+        sr = regs.status();
+    }
+
+    let errors = sr & SR_ERRORS;
+    if errors != 0 {
+        regs.clear(errors);
+    }
+    FlashFault::from_status(sr).map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays back a list of `FLASH_SR` values, the last one for good, and
+    /// advances the cycle count by `step` per read of it
+    struct Scripted {
+        script: Vec<u32>,
+        reads: usize,
+        now: u32,
+        step: u32,
+        cleared: u32,
+    }
+
+    impl Scripted {
+        fn new(script: &[u32]) -> Self {
+            Self {
+                script: script.to_vec(),
+                reads: 0,
+                now: 0,
+                step: 1,
+                cleared: 0,
+            }
+        }
+    }
+
+    impl FlashStatus for Scripted {
+        fn status(&mut self) -> u32 {
+            let i = self.reads.min(self.script.len() - 1);
+            self.reads += 1;
+            self.script[i] & !self.cleared
+        }
+
+        fn clear(&mut self, flags: u32) {
+            self.cleared |= flags;
+        }
+
+        fn cycles(&mut self) -> u32 {
+            self.now = self.now.wrapping_add(self.step);
+            self.now
+        }
+    }
+
+    #[test]
+    fn each_flag_has_its_fault() {
+        for (flag, fault) in [
+            (SR_PGSERR, FlashFault::Sequence),
+            (SR_PGPERR, FlashFault::Parallelism),
+            (SR_PGAERR, FlashFault::Alignment),
+            (SR_WRPERR, FlashFault::WriteProtected),
+            (SR_OPERR, FlashFault::Operation),
+        ] {
+            assert_eq!(FlashFault::from_status(flag), Some(fault));
+            assert_eq!(
+                wait_idle(&mut Scripted::new(&[SR_BSY, flag]), 100),
+                Err(fault)
+            );
+        }
+    }
+
+    #[test]
+    fn no_flags_no_fault() {
+        assert_eq!(FlashFault::from_status(0), None);
+        assert_eq!(FlashFault::from_status(!SR_ERRORS), None);
+        assert_eq!(
+            wait_idle(&mut Scripted::new(&[SR_BSY, SR_BSY, 0]), 100),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn earliest_flag_wins() {
+        assert_eq!(
+            FlashFault::from_status(SR_ERRORS),
+            Some(FlashFault::Sequence)
+        );
+        assert_eq!(
+            FlashFault::from_status(SR_PGAERR | SR_PGPERR | SR_OPERR),
+            Some(FlashFault::Parallelism)
+        );
+        assert_eq!(
+            FlashFault::from_status(SR_OPERR | SR_WRPERR | SR_PGAERR),
+            Some(FlashFault::Alignment)
+        );
+        assert_eq!(
+            FlashFault::from_status(SR_OPERR | SR_WRPERR),
+            Some(FlashFault::WriteProtected)
+        );
+    }
+
+    #[test]
+    fn stuck_busy_times_out() {
+        let mut regs = Scripted::new(&[SR_BSY]);
+        assert_eq!(wait_idle(&mut regs, 50), Err(FlashFault::Timeout));
+        assert!(regs.now > 50 && regs.now <= 52);
+    }
+
+    #[test]
+    fn timeout_survives_counter_wrap() {
+        let mut regs = Scripted::new(&[SR_BSY]);
+        regs.now = u32::MAX - 10;
+        assert_eq!(wait_idle(&mut regs, 50), Err(FlashFault::Timeout));
+        assert!(regs.now > 30 && regs.now < 50);
+    }
+
+    #[test]
+    fn busy_just_within_timeout_completes() {
+        let mut regs = Scripted::new(&[SR_BSY, SR_BSY, SR_BSY, 0]);
+        regs.step = 10;
+        assert_eq!(wait_idle(&mut regs, 30), Ok(()));
+    }
+
+    #[test]
+    fn fault_clears_error_flags() {
+        let mut regs = Scripted::new(&[SR_BSY, SR_PGSERR | SR_WRPERR]);
+        assert_eq!(wait_idle(&mut regs, 100), Err(FlashFault::Sequence));
+        assert_eq!(regs.cleared, SR_PGSERR | SR_WRPERR);
+        // The next operation starts clean
+        assert_eq!(wait_idle(&mut regs, 100), Ok(()));
+    }
+
+    #[test]
+    fn timeout_clears_error_flags() {
+        let mut regs = Scripted::new(&[SR_BSY | SR_OPERR]);
+        assert_eq!(wait_idle(&mut regs, 10), Err(FlashFault::Timeout));
+        assert_eq!(regs.cleared, SR_OPERR);
+    }
+
+    #[test]
+    fn success_clears_nothing() {
+        let mut regs = Scripted::new(&[0]);
+        assert_eq!(wait_idle(&mut regs, 10), Ok(()));
+        assert_eq!(regs.cleared, 0);
+    }
+}
@@ -0,0 +1,196 @@
+//! Checks on slot contents
+//!
+//! A slot is passed in as the byte slice covering it, the caller decides
+//! where those bytes come from (flash on the target, a buffer on the host).
+
+use crate::crc::Crc32;
+use crate::layout::FlashLayout;
+use boot_protocol::{BootTarget, SlotHeader, HEADER_MAGIC, HEADER_OFFSET};
+use core::mem::size_of;
+
+/// Whether `addr..addr + len` lies entirely within `start..start + size`
+pub fn range_within(addr: u32, len: u32, start: u32, size: u32) -> bool {
+    addr >= start && addr.checked_add(len).is_some_and(|end| end <= start + size)
+}
+
+/// Whether an update's declared image length is acceptable for `target`
+///
+/// The length has to be non-zero, a whole number of words (programming is
+/// word-granular) and fit the slot's [capacity](FlashLayout::slot_capacity)
+/// on `layout`: an oversized declaration would run into the next slot, or
+/// into flash the next slot's erase wipes.
+pub fn image_len_ok(layout: &FlashLayout, target: BootTarget, declared_len: u32) -> bool {
+    declared_len != 0
+        && declared_len.is_multiple_of(4)
+        && declared_len <= layout.slot_capacity(target)
+}
+
+/// Decodes the image header of `slot`, `None` if there isn't one
+pub fn parse_header(slot: &[u8]) -> Option<SlotHeader> {
+    let start = HEADER_OFFSET as usize;
+    let bytes = slot.get(start..start + size_of::<SlotHeader>())?;
+    // SlotHeader is plain words and bytes, any bit pattern is a valid value
+    let header = unsafe { (bytes.as_ptr() as *const SlotHeader).read_unaligned() };
+    (header.magic == HEADER_MAGIC).then_some(header)
+}
+
+/// Whether a stamped header's length describes an image that fits its slot
+pub fn header_len_ok(target: BootTarget, header: &SlotHeader) -> bool {
+    let min = HEADER_OFFSET + size_of::<SlotHeader>() as u32;
+    (min..=target.size()).contains(&header.image_len)
+}
+
+/// The image described by `header` as two slices around the header itself
+///
+/// Everything integrity checks cover: `[0, header)` and
+/// `[header end, image_len)` of `slot`. `header.image_len` must have been
+/// checked with `header_len_ok`.
+pub fn image_parts<'a>(slot: &'a [u8], header: &SlotHeader) -> (&'a [u8], &'a [u8]) {
+    let header_start = HEADER_OFFSET as usize;
+    let header_end = header_start + size_of::<SlotHeader>();
+    let image_end = header.image_len as usize;
+    (&slot[..header_start], &slot[header_end..image_end])
+}
+
+/// The header fields an image signature covers after the image itself:
+/// `version` then `image_len`, little-endian
+///
+/// So neither can be edited without breaking the signature, which would
+/// otherwise let an old signed image pass the anti-rollback gate with a new
+/// version. The signature field itself, the magic and the CRC stay out.
+pub fn signed_fields(header: &SlotHeader) -> [u8; 8] {
+    let mut fields = [0; 8];
+    fields[..4].copy_from_slice(&header.version.to_le_bytes());
+    fields[4..].copy_from_slice(&header.image_len.to_le_bytes());
+    fields
+}
+
+/// CRC-32 of the image described by `header`, header bytes excluded
+pub fn image_crc(slot: &[u8], header: &SlotHeader) -> u32 {
+    let (before, after) = image_parts(slot, header);
+    let mut crc = Crc32::new();
+    crc.update(before);
+    crc.update(after);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{F407_LAYOUT, F411_LAYOUT};
+
+    const HEADER_END: usize = HEADER_OFFSET as usize + size_of::<SlotHeader>();
+
+    /// A slot of `len` image bytes with a stamped header
+    fn slot(len: usize) -> (Vec<u8>, SlotHeader) {
+        let mut bytes: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let mut header = SlotHeader::unstamped(0x0001_0203);
+        header.image_len = len as u32;
+        header.crc32 = 0x1234_5678;
+        let raw = unsafe {
+            core::slice::from_raw_parts(
+                &header as *const SlotHeader as *const u8,
+                size_of::<SlotHeader>(),
+            )
+        };
+        bytes[HEADER_OFFSET as usize..HEADER_END].copy_from_slice(raw);
+        (bytes, header)
+    }
+
+    #[test]
+    fn range_within_bounds() {
+        assert!(range_within(0x100, 0x100, 0x100, 0x100));
+        assert!(range_within(0x180, 0, 0x100, 0x100));
+        assert!(!range_within(0x0FF, 4, 0x100, 0x100));
+        assert!(!range_within(0x1FD, 4, 0x100, 0x100));
+        assert!(!range_within(0xFFFF_FFFC, 8, 0x100, 0x100));
+    }
+
+    #[test]
+    fn image_len_rules() {
+        let target = BootTarget::App2;
+        let layout = &F411_LAYOUT;
+        assert!(image_len_ok(layout, target, 4));
+        assert!(image_len_ok(layout, target, target.size()));
+        assert!(!image_len_ok(layout, target, 0));
+        assert!(!image_len_ok(layout, target, 6));
+        assert!(!image_len_ok(layout, target, target.size() + 4));
+    }
+
+    #[test]
+    fn app1_len_capped_below_app2s_sector() {
+        let app1 = BootTarget::App1;
+        let capacity = 0x0802_0000 - app1.addr();
+        for layout in [&F411_LAYOUT, &F407_LAYOUT] {
+            assert!(image_len_ok(layout, app1, capacity));
+            assert!(!image_len_ok(layout, app1, capacity + 4));
+        }
+    }
+
+    #[test]
+    fn parse_header_finds_stamped_header() {
+        let (bytes, header) = slot(0x400);
+        let parsed = parse_header(&bytes).unwrap();
+        assert_eq!(parsed.version, header.version);
+        assert_eq!(parsed.image_len, 0x400);
+        assert_eq!(parsed.crc32, 0x1234_5678);
+    }
+
+    #[test]
+    fn signed_fields_are_version_then_len() {
+        let (_, mut header) = slot(0x400);
+        header.version = 0x0001_0203;
+        assert_eq!(
+            signed_fields(&header),
+            [0x03, 0x02, 0x01, 0x00, 0x00, 0x04, 0x00, 0x00]
+        );
+
+        // What isn't signed doesn't change them
+        header.crc32 ^= 1;
+        header.signature = [0xA5; 64];
+        assert_eq!(signed_fields(&header)[..4], [0x03, 0x02, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn parse_header_rejects_missing_header() {
+        let (mut bytes, _) = slot(0x400);
+        bytes[HEADER_OFFSET as usize] ^= 1;
+        assert!(parse_header(&bytes).is_none());
+        assert!(parse_header(&bytes[..HEADER_END - 1]).is_none());
+    }
+
+    #[test]
+    fn header_len_bounds() {
+        let target = BootTarget::App1;
+        let (_, mut header) = slot(0x400);
+        assert!(header_len_ok(target, &header));
+
+        header.image_len = HEADER_END as u32;
+        assert!(header_len_ok(target, &header));
+        header.image_len -= 1;
+        assert!(!header_len_ok(target, &header));
+        header.image_len = target.size() + 1;
+        assert!(!header_len_ok(target, &header));
+    }
+
+    #[test]
+    fn image_crc_skips_header() {
+        let (mut bytes, header) = slot(0x400);
+        let crc = image_crc(&bytes, &header);
+
+        let mut expected = Crc32::new();
+        expected.update(&bytes[..HEADER_OFFSET as usize]);
+        expected.update(&bytes[HEADER_END..0x400]);
+        assert_eq!(crc, expected.finish());
+
+        // Header bytes (the CRC field itself, say) don't count, image bytes do
+        bytes[HEADER_OFFSET as usize + 12] ^= 0xFF;
+        assert_eq!(image_crc(&bytes, &header), crc);
+        bytes[HEADER_END] ^= 0xFF;
+        assert_ne!(image_crc(&bytes, &header), crc);
+        // Nor does anything past image_len
+        bytes[HEADER_END] ^= 0xFF;
+        bytes.push(0xAA);
+        assert_eq!(image_crc(&bytes, &header), crc);
+    }
+}
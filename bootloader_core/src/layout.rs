@@ -0,0 +1,195 @@
+//! Flash sector geometry of the supported parts
+//!
+//! Only the sector math differs between them: every F4 flash controller has
+//! the same register layout. The bootloader picks one of these with its
+//! `device-*` feature.
+
+use boot_protocol::{BootTarget, APP2_ADDR};
+
+const KB: u32 = 1024;
+
+/// Where a part's flash sectors start and how big each one is
+pub struct FlashLayout {
+    pub base: u32,
+    /// Size of each sector, in sector-number order
+    pub sector_sizes: &'static [u32],
+}
+
+impl FlashLayout {
+    /// Sector containing `addr`, `None` outside the flash array
+    pub fn sector_of(&self, addr: u32) -> Option<u8> {
+        let mut start = self.base;
+        for (sector, &size) in self.sector_sizes.iter().enumerate() {
+            if (start..start + size).contains(&addr) {
+                return Some(sector as u8);
+            }
+            start += size;
+        }
+        None
+    }
+
+    /// Start of the sector containing `addr`, `None` outside the flash array
+    pub fn sector_start(&self, addr: u32) -> Option<u32> {
+        let sector = self.sector_of(addr)?;
+        Some(self.base + self.sector_sizes[..sector as usize].iter().sum::<u32>())
+    }
+
+    /// Bytes from the base of `target` an image may fill
+    ///
+    /// The whole slot, unless the next slot starts partway into a sector:
+    /// that sector is erased with the next slot, so the slot before it ends
+    /// where the sector starts. App2's base sits 16KB into sector 5, which
+    /// would leave App1 112KB (0x0802_0000 - 0x0800_4000) of a 128KB slot;
+    /// `layout.toml` sizes App1 to those 112KB to begin with.
+    pub fn slot_capacity(&self, target: BootTarget) -> u32 {
+        let next_base = match target {
+            BootTarget::App1 => Some(APP2_ADDR),
+            BootTarget::App2 => None,
+        };
+        let end = target.addr() + target.size();
+        let usable_end = next_base
+            .and_then(|base| self.sector_start(base))
+            .map_or(end, |start| start.clamp(target.addr(), end));
+        usable_end - target.addr()
+    }
+}
+
+/// STM32F411xE, 512KB: 4 x 16KB, 1 x 64KB, 3 x 128KB
+pub const F411_LAYOUT: FlashLayout = FlashLayout {
+    base: 0x0800_0000,
+    sector_sizes: &[
+        16 * KB,
+        16 * KB,
+        16 * KB,
+        16 * KB,
+        64 * KB,
+        128 * KB,
+        128 * KB,
+        128 * KB,
+    ],
+};
+
+/// STM32F407xG, 1MB: 4 x 16KB, 1 x 64KB, 7 x 128KB
+pub const F407_LAYOUT: FlashLayout = FlashLayout {
+    base: 0x0800_0000,
+    sector_sizes: &[
+        16 * KB,
+        16 * KB,
+        16 * KB,
+        16 * KB,
+        64 * KB,
+        128 * KB,
+        128 * KB,
+        128 * KB,
+        128 * KB,
+        128 * KB,
+        128 * KB,
+        128 * KB,
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reference manual sector start addresses, and the end of flash
+    const F411_STARTS: [u32; 9] = [
+        0x0800_0000,
+        0x0800_4000,
+        0x0800_8000,
+        0x0800_C000,
+        0x0801_0000,
+        0x0802_0000,
+        0x0804_0000,
+        0x0806_0000,
+        0x0808_0000,
+    ];
+
+    const F407_STARTS: [u32; 13] = [
+        0x0800_0000,
+        0x0800_4000,
+        0x0800_8000,
+        0x0800_C000,
+        0x0801_0000,
+        0x0802_0000,
+        0x0804_0000,
+        0x0806_0000,
+        0x0808_0000,
+        0x080A_0000,
+        0x080C_0000,
+        0x080E_0000,
+        0x0810_0000,
+    ];
+
+    fn check_bounds(layout: &FlashLayout, starts: &[u32]) {
+        assert_eq!(layout.sector_sizes.len(), starts.len() - 1);
+        for (sector, bounds) in starts.windows(2).enumerate() {
+            let (start, end) = (bounds[0], bounds[1] - 1);
+            let sector = Some(sector as u8);
+            assert_eq!(layout.sector_of(start), sector, "{start:#X}");
+            assert_eq!(layout.sector_of(end), sector, "{end:#X}");
+        }
+    }
+
+    #[test]
+    fn f411_sector_bounds() {
+        check_bounds(&F411_LAYOUT, &F411_STARTS);
+    }
+
+    #[test]
+    fn f407_sector_bounds() {
+        check_bounds(&F407_LAYOUT, &F407_STARTS);
+    }
+
+    #[test]
+    fn step_from_64k_to_128k() {
+        for layout in [&F411_LAYOUT, &F407_LAYOUT] {
+            assert_eq!(layout.sector_of(0x0801_FFFF), Some(4));
+            assert_eq!(layout.sector_of(0x0802_0000), Some(5));
+            assert_eq!(layout.sector_of(0x0803_FFFF), Some(5));
+            assert_eq!(layout.sector_of(0x0804_0000), Some(6));
+        }
+    }
+
+    #[test]
+    fn sector_starts() {
+        for layout in [&F411_LAYOUT, &F407_LAYOUT] {
+            assert_eq!(layout.sector_start(0x0800_0000), Some(0x0800_0000));
+            assert_eq!(layout.sector_start(0x0800_7FFF), Some(0x0800_4000));
+            assert_eq!(layout.sector_start(0x0802_4000), Some(0x0802_0000));
+            assert_eq!(layout.sector_start(0x0807_FFFF), Some(0x0806_0000));
+            assert_eq!(layout.sector_start(0x0700_0000), None);
+        }
+        assert_eq!(F411_LAYOUT.sector_start(0x0808_0000), None);
+        assert_eq!(F407_LAYOUT.sector_start(0x080F_FFFF), Some(0x080E_0000));
+    }
+
+    #[test]
+    fn app1_stops_at_app2s_first_sector() {
+        for layout in [&F411_LAYOUT, &F407_LAYOUT] {
+            let app2 = BootTarget::App2;
+            assert_eq!(layout.slot_capacity(BootTarget::App1), 112 * KB);
+            assert_eq!(layout.slot_capacity(app2), app2.size());
+        }
+    }
+
+    #[test]
+    fn layout_toml_links_app1_within_its_capacity() {
+        // Otherwise an App1 the linker accepts could be erased by an App2
+        // update
+        let app1 = BootTarget::App1;
+        for layout in [&F411_LAYOUT, &F407_LAYOUT] {
+            assert_eq!(layout.slot_capacity(app1), app1.size());
+        }
+    }
+
+    #[test]
+    fn outside_flash_is_none() {
+        for (layout, end) in [(&F411_LAYOUT, 0x0808_0000), (&F407_LAYOUT, 0x0810_0000)] {
+            assert_eq!(layout.sector_of(layout.base - 1), None);
+            assert_eq!(layout.sector_of(end), None);
+            assert_eq!(layout.sector_of(0), None);
+            assert_eq!(layout.sector_of(u32::MAX), None);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Hardware-free logic of the bootloader
+//!
+//! Which slot to boot, whether an image fits and checks out, CRC, what the
+//! flash controller's status flags mean, where the sectors lie, which
+//! settings record is current. Everything
+//! here works on plain values and byte slices, the bootloader binary only
+//! reads flash and registers and hands the results in. That keeps the crate
+//! buildable for the host, where its unit tests run:
+//! `cargo test -p bootloader_core --target x86_64-unknown-linux-gnu` (the
+//! workspace builds for the target by default). `boot_protocol` comes along,
+//! its `cortex-m` calls build on the host too but are never reached from here.
+
+#![cfg_attr(not(test), no_std)]
+
+mod crc;
+mod flash_status;
+mod image;
+mod layout;
+mod select;
+mod settings_log;
+
+pub use crc::{crc16_xmodem, Crc32};
+pub use flash_status::{wait_idle, FlashFault, FlashStatus};
+pub use image::{
+    header_len_ok, image_crc, image_len_ok, image_parts, parse_header, range_within, signed_fields,
+};
+pub use layout::{FlashLayout, F407_LAYOUT, F411_LAYOUT};
+pub use select::{
+    choose_slot, decide_boot, fallback, select_app, BootError, BootPolicy, RECOVERY_SLOT,
+};
+pub use settings_log::{latest_record, next_free, ERASED};
@@ -0,0 +1,262 @@
+//! Boot slot selection
+
+use boot_protocol::{BootRequest, BootTarget, SlotRoles};
+use core::fmt;
+
+/// Role booted when nothing else is asked for and no default is set, and the
+/// one to recover to
+///
+/// A role, not a slot: with the roles swapped (see [`SlotRoles`]) the App2
+/// slot plays it. Servicing commands refuse to erase the slot playing it.
+pub const RECOVERY_SLOT: BootTarget = BootTarget::App1;
+
+/// Picks the application requested by a given magic value, `default` if it
+/// doesn't ask for one
+pub fn select_app(magic: u32, default: BootTarget) -> BootTarget {
+    match BootRequest::from_magic(magic) {
+        Some(BootRequest::App(target)) => target,
+        _ => default,
+    }
+}
+
+/// The slot tried when `target` can't be booted
+pub const fn fallback(target: BootTarget) -> BootTarget {
+    match target {
+        BootTarget::App1 => BootTarget::App2,
+        BootTarget::App2 => BootTarget::App1,
+    }
+}
+
+/// The requested slot if `bootable` accepts it, otherwise its fallback
+///
+/// `None` when neither slot is bootable; `bootable` is asked about the
+/// fallback only if the requested slot was rejected.
+pub fn choose_slot(
+    requested: BootTarget,
+    mut bootable: impl FnMut(BootTarget) -> bool,
+) -> Option<BootTarget> {
+    [requested, fallback(requested)]
+        .into_iter()
+        .find(|&target| bootable(target))
+}
+
+/// Why [`decide_boot`] found nothing to jump to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BootError {
+    /// Neither slot (or, pinned, the forced slot) passed validation
+    NoBootableImage,
+}
+
+impl fmt::Display for BootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootError::NoBootableImage => f.write_str("no bootable image"),
+        }
+    }
+}
+
+/// Build and operator settings that steer [`decide_boot`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BootPolicy {
+    /// Slot booted when the magic asks for no app
+    pub default: BootTarget,
+    /// Which slot plays the role an app request or the storm guard asks for
+    pub roles: SlotRoles,
+    /// Pinned SKU: the only slot ever booted, the magic and storm guard don't
+    /// apply and there is no fallback
+    pub forced: Option<BootTarget>,
+    /// Slot whose app didn't come up last time: tried after the other slot
+    /// instead of before it
+    pub suspect: Option<BootTarget>,
+}
+
+impl Default for BootPolicy {
+    fn default() -> Self {
+        Self {
+            default: RECOVERY_SLOT,
+            roles: SlotRoles::IDENTITY,
+            forced: None,
+            suspect: None,
+        }
+    }
+}
+
+/// The whole app-boot decision: the address to jump to for `magic`
+///
+/// Service requests (self-test, update mode) are the caller's to handle
+/// first. `storm` is asked only when the magic is an app switch request and
+/// diverts it to the slot playing [`RECOVERY_SLOT`] when it returns `true`; a suspect slot
+/// picked either way gives way to its fallback; `bootable` is the
+/// validation, see [`choose_slot`]. Returns instead of jumping, so the
+/// bootloader decides here and diverges through its `Jumper`, and host tests
+/// can check which address a given magic leads to.
+pub fn decide_boot(
+    magic: u32,
+    policy: &BootPolicy,
+    storm: impl FnOnce() -> bool,
+    mut bootable: impl FnMut(BootTarget) -> bool,
+) -> Result<u32, BootError> {
+    if let Some(forced) = policy.forced {
+        return if bootable(forced) {
+            Ok(forced.addr())
+        } else {
+            Err(BootError::NoBootableImage)
+        };
+    }
+
+    // Requests name roles, the default is a slot
+    let mut requested = match BootRequest::from_magic(magic) {
+        Some(BootRequest::App(role)) => policy.roles.slot(role),
+        _ => policy.default,
+    };
+    if matches!(BootRequest::from_magic(magic), Some(BootRequest::App(_))) && storm() {
+        requested = policy.roles.slot(RECOVERY_SLOT);
+    }
+    if policy.suspect == Some(requested) {
+        requested = fallback(requested);
+    }
+
+    choose_slot(requested, bootable)
+        .map(BootTarget::addr)
+        .ok_or(BootError::NoBootableImage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boot_protocol::{MAGIC_APP1, MAGIC_APP2, MAGIC_SELF_TEST, MAGIC_UPDATE};
+
+    fn any(_: BootTarget) -> bool {
+        true
+    }
+
+    #[test]
+    fn select_app_follows_magic() {
+        assert_eq!(select_app(MAGIC_APP1, BootTarget::App2), BootTarget::App1);
+        assert_eq!(select_app(MAGIC_APP2, BootTarget::App1), BootTarget::App2);
+    }
+
+    #[test]
+    fn select_app_defaults_without_app_request() {
+        for magic in [0, 0xFFFF_FFFF, MAGIC_UPDATE, MAGIC_SELF_TEST] {
+            assert_eq!(select_app(magic, BootTarget::App2), BootTarget::App2);
+        }
+    }
+
+    #[test]
+    fn choose_slot_prefers_requested() {
+        let mut asked = Vec::new();
+        let chosen = choose_slot(BootTarget::App2, |target| {
+            asked.push(target);
+            true
+        });
+        assert_eq!(chosen, Some(BootTarget::App2));
+        assert_eq!(asked, [BootTarget::App2]);
+    }
+
+    #[test]
+    fn choose_slot_falls_back() {
+        let chosen = choose_slot(BootTarget::App1, |target| target == BootTarget::App2);
+        assert_eq!(chosen, Some(BootTarget::App2));
+        assert_eq!(choose_slot(BootTarget::App1, |_| false), None);
+    }
+
+    #[test]
+    fn decide_boot_default_and_request() {
+        let policy = BootPolicy::default();
+        assert_eq!(
+            decide_boot(0, &policy, || false, any),
+            Ok(BootTarget::App1.addr())
+        );
+        assert_eq!(
+            decide_boot(MAGIC_APP2, &policy, || false, any),
+            Ok(BootTarget::App2.addr())
+        );
+
+        let policy = BootPolicy {
+            default: BootTarget::App2,
+            ..BootPolicy::default()
+        };
+        assert_eq!(
+            decide_boot(0, &policy, || false, any),
+            Ok(BootTarget::App2.addr())
+        );
+    }
+
+    #[test]
+    fn decide_boot_asks_storm_only_for_app_requests() {
+        let policy = BootPolicy::default();
+        let storm = || panic!("storm guard asked without an app request");
+        assert!(decide_boot(0, &policy, storm, any).is_ok());
+
+        assert_eq!(
+            decide_boot(MAGIC_APP2, &policy, || true, any),
+            Ok(RECOVERY_SLOT.addr())
+        );
+    }
+
+    #[test]
+    fn decide_boot_maps_roles() {
+        let policy = BootPolicy {
+            roles: SlotRoles::IDENTITY.swapped(),
+            ..BootPolicy::default()
+        };
+        assert_eq!(
+            decide_boot(MAGIC_APP1, &policy, || false, any),
+            Ok(BootTarget::App2.addr())
+        );
+        // A storm recovers to the slot playing App1's role
+        assert_eq!(
+            decide_boot(MAGIC_APP1, &policy, || true, any),
+            Ok(BootTarget::App2.addr())
+        );
+        // The default names a slot, not a role
+        assert_eq!(
+            decide_boot(0, &policy, || false, any),
+            Ok(BootTarget::App1.addr())
+        );
+    }
+
+    #[test]
+    fn decide_boot_passes_over_suspect() {
+        let policy = BootPolicy {
+            suspect: Some(BootTarget::App2),
+            ..BootPolicy::default()
+        };
+        assert_eq!(
+            decide_boot(MAGIC_APP2, &policy, || false, any),
+            Ok(BootTarget::App1.addr())
+        );
+        // Still booted when it's the only one left
+        assert_eq!(
+            decide_boot(MAGIC_APP2, &policy, || false, |t| t == BootTarget::App2),
+            Ok(BootTarget::App2.addr())
+        );
+    }
+
+    #[test]
+    fn decide_boot_forced_has_no_fallback() {
+        let policy = BootPolicy {
+            forced: Some(BootTarget::App2),
+            ..BootPolicy::default()
+        };
+        let storm = || panic!("storm guard asked for a pinned SKU");
+        assert_eq!(
+            decide_boot(MAGIC_APP1, &policy, storm, any),
+            Ok(BootTarget::App2.addr())
+        );
+        assert_eq!(
+            decide_boot(0, &policy, || false, |t| t == BootTarget::App1),
+            Err(BootError::NoBootableImage)
+        );
+    }
+
+    #[test]
+    fn decide_boot_nothing_bootable() {
+        let policy = BootPolicy::default();
+        assert_eq!(
+            decide_boot(MAGIC_APP1, &policy, || false, |_| false),
+            Err(BootError::NoBootableImage)
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! Finding the newest record in the settings log
+//!
+//! The bootloader keeps its settings record in a backup register, which loses
+//! it with the power unless VBAT is supplied. Every record it stores is also
+//! appended to the settings log, a few erased words of flash at the top of
+//! its own sector, so a power cycle without a battery can bring it back.
+//! Words there are only ever programmed, never erased (erasing would take the
+//! bootloader with it): the newest record is the last one written, and the
+//! log is full after as many changes as it has words. Reflashing the
+//! bootloader erases the sector and empties it again.
+//!
+//! A word a power loss left half programmed fails the record's validity
+//! check and is skipped, the record before it applies.
+
+/// An erased flash word
+pub const ERASED: u32 = 0xFFFF_FFFF;
+
+/// The newest word of `log` that `is_valid` accepts as a record
+pub fn latest_record(log: &[u32], is_valid: impl Fn(u32) -> bool) -> Option<u32> {
+    log.iter()
+        .rev()
+        .copied()
+        .find(|&word| word != ERASED && is_valid(word))
+}
+
+/// Index of the word the next record goes to, `None` once the log is full
+///
+/// The word past the last one written, valid or not, so the records stay in
+/// the order they were written in.
+pub fn next_free(log: &[u32]) -> Option<usize> {
+    let used = log
+        .iter()
+        .rposition(|&word| word != ERASED)
+        .map_or(0, |last| last + 1);
+    (used < log.len()).then_some(used)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAG: u32 = 0xD5F7_0000;
+
+    fn valid(word: u32) -> bool {
+        word & 0xFFFF_0000 == TAG
+    }
+
+    #[test]
+    fn empty_log() {
+        let log = [ERASED; 4];
+        assert_eq!(latest_record(&log, valid), None);
+        assert_eq!(next_free(&log), Some(0));
+    }
+
+    #[test]
+    fn newest_record_wins() {
+        let log = [TAG | 1, TAG | 2, TAG | 0x11, ERASED];
+        assert_eq!(latest_record(&log, valid), Some(TAG | 0x11));
+        assert_eq!(next_free(&log), Some(3));
+    }
+
+    #[test]
+    fn torn_word_is_skipped() {
+        // The last write was cut short: the one before it applies, and the
+        // next one goes past the torn word
+        let log = [TAG | 1, TAG | 2, 0xF5F7_FFF1, ERASED];
+        assert_eq!(latest_record(&log, valid), Some(TAG | 2));
+        assert_eq!(next_free(&log), Some(3));
+    }
+
+    #[test]
+    fn full_log() {
+        let log = [TAG | 1, TAG | 2];
+        assert_eq!(latest_record(&log, valid), Some(TAG | 2));
+        assert_eq!(next_free(&log), None);
+        assert_eq!(next_free(&[]), None);
+    }
+}
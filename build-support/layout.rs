@@ -0,0 +1,243 @@
+//! `layout.toml`, as read by the build scripts
+//!
+//! Shared by every crate's `build.rs` through `#[path]`, so all images and
+//! `boot_protocol`'s constants are generated from the same numbers; `xtask`
+//! places the images in the combined flash file with it too. Only the
+//! subset of TOML the file uses is understood: `[section]` headers and
+//! `key = integer` lines, with `#` comments and `_` digit separators.
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A block of memory, `size` bytes from `origin`
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub origin: u32,
+    pub size: u32,
+}
+
+impl Region {
+    pub fn end(&self) -> u32 {
+        self.origin + self.size
+    }
+
+    pub fn contains(&self, other: &Region) -> bool {
+        other.origin >= self.origin && other.end() <= self.end()
+    }
+}
+
+pub struct Layout {
+    pub flash: Region,
+    pub bootloader: Region,
+    /// Top of the bootloader's region, left out of its image: the settings
+    /// log
+    pub settings: Region,
+    pub app1: Region,
+    pub app2: Region,
+    pub ram: Region,
+    /// Bytes at the very top of RAM: boot magic and reset cause
+    pub noinit: u32,
+    /// Bytes right below them: the app heartbeat
+    pub heartbeat: u32,
+    /// Bytes below those: the apps' panic ring
+    pub panic_log: u32,
+    /// Gap between every image's stack top and the reserved words
+    pub stack_guard: u32,
+}
+
+impl Layout {
+    /// Reads and checks the workspace's `layout.toml`, and has Cargo rerun the
+    /// build script when it changes
+    pub fn load() -> Layout {
+        let manifest = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+        let path = manifest.join("../layout.toml");
+        println!("cargo:rerun-if-changed={}", path.display());
+        println!("cargo:rerun-if-changed=../build-support/layout.rs");
+        Self::read(&path)
+    }
+
+    /// Reads and checks the layout file at `path`
+    pub fn read(path: &Path) -> Layout {
+        let text = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("can't read {}: {e}", path.display()));
+        let values = parse(&text);
+        let get = |section: &str, key: &str| -> u32 {
+            *values
+                .get(&(section.to_string(), key.to_string()))
+                .unwrap_or_else(|| panic!("layout.toml: missing {section}.{key}"))
+        };
+        let region = |section: &str| Region {
+            origin: get(section, "origin"),
+            size: get(section, "size"),
+        };
+
+        let flash = region("flash");
+        let bootloader = Region {
+            origin: flash.origin,
+            size: get("bootloader", "size"),
+        };
+        let settings = get("bootloader", "settings");
+        let layout = Layout {
+            flash,
+            bootloader,
+            settings: Region {
+                origin: bootloader.end().wrapping_sub(settings),
+                size: settings,
+            },
+            app1: region("app1"),
+            app2: region("app2"),
+            ram: region("ram"),
+            noinit: get("ram", "noinit"),
+            heartbeat: get("ram", "heartbeat"),
+            panic_log: get("ram", "panic_log"),
+            stack_guard: get("ram", "stack_guard"),
+        };
+        layout.check();
+        layout
+    }
+
+    fn check(&self) {
+        let slots = [("app1", self.app1), ("app2", self.app2)];
+        for (name, slot) in slots {
+            assert!(
+                self.flash.contains(&slot),
+                "layout.toml: {name} outside flash"
+            );
+            assert!(
+                slot.origin.is_multiple_of(1024),
+                "layout.toml: {name} not 1K aligned"
+            );
+            assert!(
+                slot.origin >= self.bootloader.end(),
+                "layout.toml: {name} overlaps the bootloader"
+            );
+        }
+        assert!(
+            self.settings.size > 0 && self.settings.size.is_multiple_of(4),
+            "layout.toml: bootloader.settings not a whole number of words"
+        );
+        assert!(
+            self.settings.size < self.bootloader.size,
+            "layout.toml: bootloader.settings leaves no room for the bootloader"
+        );
+        assert!(
+            self.app1.end() <= self.app2.origin || self.app2.end() <= self.app1.origin,
+            "layout.toml: app1 and app2 overlap"
+        );
+        assert!(
+            self.noinit == 8,
+            "layout.toml: noinit holds exactly two words"
+        );
+        assert!(
+            self.heartbeat == 8,
+            "layout.toml: heartbeat holds exactly two words"
+        );
+        assert!(
+            self.panic_log.is_multiple_of(4),
+            "layout.toml: panic_log not word sized"
+        );
+        assert!(
+            self.stack_guard.is_multiple_of(8),
+            "layout.toml: stack_guard not a multiple of 8"
+        );
+        assert!(
+            self.image_ram().size >= self.ram.size / 2,
+            "layout.toml: reserved words take up half of RAM"
+        );
+    }
+
+    /// What the bootloader image may occupy: its region below the settings
+    /// log
+    pub fn bootloader_code(&self) -> Region {
+        Region {
+            origin: self.bootloader.origin,
+            size: self.bootloader.size - self.settings.size,
+        }
+    }
+
+    /// Address of the boot magic, the first noinit word
+    pub fn magic_addr(&self) -> u32 {
+        self.ram.end() - self.noinit
+    }
+
+    /// Address of the heartbeat counter, right below the noinit words
+    pub fn heartbeat_addr(&self) -> u32 {
+        self.magic_addr() - self.heartbeat
+    }
+
+    /// Start of the panic ring, right below the heartbeat
+    pub fn panic_log_addr(&self) -> u32 {
+        self.heartbeat_addr() - self.panic_log
+    }
+
+    /// Lowest reserved word, the start of the panic ring
+    pub fn reserved_start(&self) -> u32 {
+        self.panic_log_addr()
+    }
+
+    /// What an image may use as RAM: everything below the stack guard under
+    /// the reserved words. Every stack starts at its top
+    pub fn image_ram(&self) -> Region {
+        Region {
+            origin: self.ram.origin,
+            size: self.reserved_start() - self.stack_guard - self.ram.origin,
+        }
+    }
+
+    /// Writes `OUT_DIR/memory.x`: a `MEMORY` block with `FLASH` at `flash`,
+    /// `RAM` and any `extra` regions, followed by the crate's `sections.x`
+    pub fn write_memory_x(&self, flash: Region, extra: &[(&str, Region)]) {
+        let ram = self.image_ram();
+        let mut memory = String::from("/* Generated by build.rs from layout.toml */\nMEMORY\n{\n");
+        let regions = [("FLASH", flash), ("RAM", ram)];
+        for (name, region) in regions.iter().chain(extra) {
+            memory += &format!(
+                "  {name} : ORIGIN = {:#010X}, LENGTH = {:#X}\n",
+                region.origin, region.size
+            );
+        }
+        memory += "}\n\n";
+        // For the sections.x assertion that the stack stays clear of them
+        memory += &format!(
+            "_reserved_ram_start = {:#010X};\n_stack_guard_size = {:#X};\n\n",
+            self.reserved_start(),
+            self.stack_guard
+        );
+
+        println!("cargo:rerun-if-changed=sections.x");
+        memory += &fs::read_to_string("sections.x").expect("can't read sections.x");
+        let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+        fs::write(out.join("memory.x"), memory).unwrap();
+        println!("cargo:rustc-link-search={}", out.display());
+    }
+}
+
+fn parse(text: &str) -> HashMap<(String, String), u32> {
+    let mut values = HashMap::new();
+    let mut section = String::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .unwrap_or_else(|| panic!("layout.toml:{}: expected key = value", number + 1));
+        let value = value.trim().replace('_', "");
+        let value = match value.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => value.parse(),
+        }
+        .unwrap_or_else(|_| panic!("layout.toml:{}: not an integer", number + 1));
+        values.insert((section.clone(), key.trim().to_string()), value);
+    }
+    values
+}
@@ -0,0 +1,89 @@
+//! Just enough ELF to get an image's flash contents out of it
+//!
+//! The images are 32-bit little-endian ARM executables. What ends up in flash
+//! is every `PT_LOAD` segment's file bytes, placed at its physical address
+//! (the load address, so `.data` lands in flash behind the code rather than
+//! at its RAM address). `.bss` and the noinit sections have no file bytes and
+//! drop out on their own.
+
+use std::fmt;
+
+const PT_LOAD: u32 = 1;
+const EM_ARM: u16 = 40;
+
+/// Bytes to be written at `addr`
+pub struct Segment {
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    pub fn end(&self) -> u32 {
+        self.addr + self.data.len() as u32
+    }
+}
+
+#[derive(Debug)]
+pub enum ElfError {
+    NotElf,
+    /// Not a 32-bit little-endian ARM executable
+    Unsupported,
+    /// A header or segment points past the end of the file
+    Truncated,
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::NotElf => f.write_str("not an ELF file"),
+            ElfError::Unsupported => f.write_str("not a 32-bit little-endian ARM ELF"),
+            ElfError::Truncated => f.write_str("truncated ELF"),
+        }
+    }
+}
+
+fn u16_at(file: &[u8], offset: usize) -> Result<u16, ElfError> {
+    let bytes = file.get(offset..offset + 2).ok_or(ElfError::Truncated)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(file: &[u8], offset: usize) -> Result<u32, ElfError> {
+    let bytes = file.get(offset..offset + 4).ok_or(ElfError::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The loadable contents of `file`, sorted by address
+pub fn load_segments(file: &[u8]) -> Result<Vec<Segment>, ElfError> {
+    if file.get(..4) != Some(b"\x7fELF") {
+        return Err(ElfError::NotElf);
+    }
+    // EI_CLASS 1: 32-bit, EI_DATA 1: little-endian
+    if file.get(4..6) != Some(&[1, 1]) || u16_at(file, 18)? != EM_ARM {
+        return Err(ElfError::Unsupported);
+    }
+
+    let phoff = u32_at(file, 28)? as usize;
+    let phentsize = u16_at(file, 42)? as usize;
+    let phnum = u16_at(file, 44)? as usize;
+
+    let mut segments = Vec::new();
+    for index in 0..phnum {
+        let header = phoff + index * phentsize;
+        let p_type = u32_at(file, header)?;
+        let p_offset = u32_at(file, header + 4)? as usize;
+        let p_paddr = u32_at(file, header + 12)?;
+        let p_filesz = u32_at(file, header + 16)? as usize;
+        if p_type != PT_LOAD || p_filesz == 0 {
+            continue;
+        }
+        let data = file
+            .get(p_offset..p_offset + p_filesz)
+            .ok_or(ElfError::Truncated)?;
+        segments.push(Segment {
+            addr: p_paddr,
+            data: data.to_vec(),
+        });
+    }
+    segments.sort_by_key(|segment| segment.addr);
+    Ok(segments)
+}
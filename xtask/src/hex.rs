@@ -0,0 +1,48 @@
+//! Intel HEX output
+//!
+//! Data records of up to 16 bytes, with an extended linear address record
+//! whenever the upper 16 address bits change. Only the segments themselves
+//! are written, so a programmer leaves the gaps between images alone.
+
+use crate::elf::Segment;
+use std::fmt::Write;
+
+const BYTES_PER_RECORD: usize = 16;
+
+fn record(out: &mut String, kind: u8, addr: u16, data: &[u8]) {
+    let [addr_hi, addr_lo] = addr.to_be_bytes();
+    let mut sum = (data.len() as u8)
+        .wrapping_add(addr_hi)
+        .wrapping_add(addr_lo)
+        .wrapping_add(kind);
+    write!(out, ":{:02X}{:04X}{:02X}", data.len(), addr, kind).unwrap();
+    for &byte in data {
+        write!(out, "{byte:02X}").unwrap();
+        sum = sum.wrapping_add(byte);
+    }
+    writeln!(out, "{:02X}", sum.wrapping_neg()).unwrap();
+}
+
+/// `segments` as an Intel HEX file
+pub fn to_hex(segments: &[&Segment]) -> String {
+    let mut out = String::new();
+    let mut upper = None;
+    for segment in segments {
+        let mut addr = segment.addr;
+        let mut data = &segment.data[..];
+        while !data.is_empty() {
+            if upper != Some(addr >> 16) {
+                upper = Some(addr >> 16);
+                record(&mut out, 0x04, 0, &((addr >> 16) as u16).to_be_bytes());
+            }
+            // A record never crosses a 64K boundary
+            let to_boundary = 0x1_0000 - (addr & 0xFFFF) as usize;
+            let len = data.len().min(BYTES_PER_RECORD).min(to_boundary);
+            record(&mut out, 0x00, addr as u16, &data[..len]);
+            addr += len as u32;
+            data = &data[len..];
+        }
+    }
+    record(&mut out, 0x01, 0, &[]);
+    out
+}
@@ -0,0 +1,271 @@
+//! Workspace tasks, run from anywhere in the workspace as `cargo xtask <task>`
+//!
+//! - `image [--debug]` builds the bootloader and both apps (release unless
+//!   `--debug`), stamps the apps' slot headers (see `stamp`, signed when
+//!   `BOOT_SIGNING_KEY` names a key) and writes `target/flash/combined.bin`
+//!   and `combined.hex`, every image at its address from `layout.toml`, plus
+//!   `app1.bin`/`app2.bin` for update mode. Then it runs `verify` on the
+//!   result.
+//! - `verify [--debug]` re-reads `combined.bin` and checks that each image's
+//!   ELF contents sit at their offsets and that each app's stamped CRC
+//!   matches, printing where each one starts.
+//!
+//! The `.bin` starts at the beginning of flash and fills the gaps between
+//! images with 0xFF, like erased flash, so it can be written in one go:
+//!
+//! ```text
+//! probe-rs download target/flash/combined.bin --binary-format bin \
+//!   --chip STM32F411CEUx --base-address 0x08000000
+//! ```
+
+mod elf;
+mod hex;
+#[path = "../../build-support/layout.rs"]
+mod layout;
+mod stamp;
+
+use boot_protocol::{SlotHeader, HEADER_OFFSET};
+use elf::Segment;
+use layout::{Layout, Region};
+use std::env;
+use std::fs;
+use std::mem::size_of;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// The embedded target from `.cargo/config.toml`
+const TARGET: &str = "thumbv7em-none-eabihf";
+
+/// Value of erased flash, used for the padding
+const ERASED: u8 = 0xFF;
+
+type Result<T> = std::result::Result<T, String>;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let debug = args.iter().any(|arg| arg == "--debug");
+    let result = match args.first().map(String::as_str) {
+        Some("image") => image(debug),
+        Some("verify") => verify(debug),
+        _ => Err("usage: cargo xtask image|verify [--debug]".to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("xtask: {e}");
+        process::exit(1);
+    }
+}
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .to_path_buf()
+}
+
+fn target_dir() -> PathBuf {
+    env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| workspace_root().join("target"))
+}
+
+fn output_dir() -> PathBuf {
+    target_dir().join("flash")
+}
+
+/// The images that go into the combined file and the flash each may occupy
+fn parts(layout: &Layout) -> [(&'static str, Region); 3] {
+    [
+        ("bootloader", layout.bootloader_code()),
+        ("app1", layout.app1),
+        ("app2", layout.app2),
+    ]
+}
+
+/// Whether `package` is an app, whose slot header gets stamped
+fn is_app(package: &str) -> bool {
+    package != "bootloader"
+}
+
+/// Where the slot header lies in an app's image
+fn header_range() -> Range<usize> {
+    let start = HEADER_OFFSET as usize;
+    start..start + size_of::<SlotHeader>()
+}
+
+fn build(package: &str, debug: bool) -> Result<()> {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut command = Command::new(cargo);
+    command
+        .current_dir(workspace_root())
+        .args(["build", "-p", package]);
+    if !debug {
+        command.arg("--release");
+    }
+    let status = command
+        .status()
+        .map_err(|e| format!("can't run cargo: {e}"))?;
+    if !status.success() {
+        return Err(format!("building {package} failed"));
+    }
+    Ok(())
+}
+
+/// Flash contents of `package`'s ELF, checked to fit `region`
+fn load(package: &str, region: Region, debug: bool) -> Result<Vec<Segment>> {
+    let profile = if debug { "debug" } else { "release" };
+    let path = target_dir().join(TARGET).join(profile).join(package);
+    let file = fs::read(&path).map_err(|e| format!("can't read {}: {e}", path.display()))?;
+    let segments = elf::load_segments(&file).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    if segments.is_empty() {
+        return Err(format!("{package}: nothing to load"));
+    }
+    for segment in &segments {
+        let fits = Region {
+            origin: segment.addr,
+            size: segment.data.len() as u32,
+        };
+        if !region.contains(&fits) {
+            return Err(format!(
+                "{package}: {:#010x}..{:#010x} is outside {:#010x}..{:#010x} \
+                 (a standalone build?)",
+                segment.addr,
+                segment.end(),
+                region.origin,
+                region.end()
+            ));
+        }
+    }
+    Ok(segments)
+}
+
+fn image(debug: bool) -> Result<()> {
+    let layout = Layout::read(&workspace_root().join("layout.toml"));
+    let key = stamp::signing_key()?;
+    if key.is_none() {
+        println!(
+            "{} not set, the apps are left unsigned",
+            stamp::SIGNING_KEY_VAR
+        );
+    }
+
+    let out = output_dir();
+    fs::create_dir_all(&out).map_err(|e| format!("can't create {}: {e}", out.display()))?;
+    let write = |name: &str, contents: &[u8]| {
+        let path = out.join(name);
+        fs::write(&path, contents).map_err(|e| format!("can't write {}: {e}", path.display()))
+    };
+
+    let mut segments = Vec::new();
+    for (package, region) in parts(&layout) {
+        build(package, debug)?;
+        let loaded = load(package, region, debug)?;
+        if !is_app(package) {
+            segments.extend(loaded);
+            continue;
+        }
+        // One block per app with its header filled in, also on its own for
+        // update mode's `ymodem`
+        let image = stamp::stamp(&loaded, region.origin, key.as_ref())
+            .map_err(|e| format!("{package}: {e}"))?;
+        write(&format!("{package}.bin"), &image.data)?;
+        segments.push(image);
+    }
+    segments.sort_by_key(|segment| segment.addr);
+
+    // The regions don't overlap (`Layout::read` checks), so neither do these
+    let end = segments.iter().map(Segment::end).max().unwrap();
+    let mut bin = vec![ERASED; (end - layout.flash.origin) as usize];
+    for segment in &segments {
+        let offset = (segment.addr - layout.flash.origin) as usize;
+        bin[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+    }
+
+    write("combined.bin", &bin)?;
+    let all: Vec<&Segment> = segments.iter().collect();
+    write("combined.hex", hex::to_hex(&all).as_bytes())?;
+    println!(
+        "wrote {} ({} bytes from {:#010x}) and combined.hex",
+        out.join("combined.bin").display(),
+        bin.len(),
+        layout.flash.origin
+    );
+
+    verify(debug)
+}
+
+/// Checks the combined file on disk against the images it was made from
+fn verify(debug: bool) -> Result<()> {
+    let layout = Layout::read(&workspace_root().join("layout.toml"));
+    let path = output_dir().join("combined.bin");
+    let bin = fs::read(&path).map_err(|e| format!("can't read {}: {e}", path.display()))?;
+
+    let mut mismatches = 0;
+    for (package, region) in parts(&layout) {
+        let segments = load(package, region, debug)?;
+        let size: usize = segments.iter().map(|segment| segment.data.len()).sum();
+        // An app's header was stamped after the link, it differs by design
+        let in_header = |addr: u32| {
+            is_app(package) && header_range().contains(&((addr - region.origin) as usize))
+        };
+        let intact = segments.iter().all(|segment| {
+            let offset = (segment.addr - layout.flash.origin) as usize;
+            bin.get(offset..offset + segment.data.len())
+                .is_some_and(|bytes| {
+                    (segment.addr..)
+                        .zip(bytes.iter().zip(&segment.data))
+                        .all(|(addr, (a, b))| a == b || in_header(addr))
+                })
+        });
+        let base = (region.origin - layout.flash.origin) as usize;
+        let header = if is_app(package) {
+            let slot = bin.get(base..).unwrap_or_default();
+            stamp_status(&slot[..slot.len().min(region.size as usize)])
+        } else {
+            Ok("")
+        };
+
+        // The first two vector table words, what the bootloader checks first
+        let word = |at: usize| {
+            bin.get(at..at + 4)
+                .map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        println!(
+            "{package:<10} @ {:#010x}  {size:>6} bytes  sp {:#010x} reset {:#010x}  {}  {}",
+            region.origin,
+            word(base),
+            word(base + 4),
+            if intact { "ok" } else { "MISMATCH" },
+            header.unwrap_or_else(|problem| problem)
+        );
+        if !intact || header.is_err() {
+            mismatches += 1;
+        }
+    }
+
+    if mismatches > 0 {
+        return Err(format!("{} doesn't match the built images", path.display()));
+    }
+    Ok(())
+}
+
+/// What the slot header at the start of `slot` says about the image, an
+/// error for one the bootloader would refuse to check
+fn stamp_status(slot: &[u8]) -> std::result::Result<&'static str, &'static str> {
+    let header = bootloader_core::parse_header(slot).ok_or("NO HEADER")?;
+    if !header.is_stamped() {
+        return Err("UNSTAMPED");
+    }
+    if header.image_len as usize > slot.len() || (header.image_len as usize) < header_range().end {
+        return Err("BAD LENGTH");
+    }
+    if bootloader_core::image_crc(slot, &header) != header.crc32 {
+        return Err("CRC MISMATCH");
+    }
+    Ok(if header.signature == [0; 64] {
+        "crc ok, unsigned"
+    } else {
+        "crc ok, signed"
+    })
+}
@@ -0,0 +1,82 @@
+//! Filling in an app's slot header after the link
+//!
+//! `image_len`, `crc32` and `signature` depend on the linked image, so the
+//! build leaves them at zero (`boot_protocol::slot_header!`). Here the app's
+//! flash contents are laid out from its slot base as one block, padded with
+//! erased bytes to a whole word (what flash holds there anyway), and the
+//! header at `HEADER_OFFSET` gets the block's length, its CRC and, with a
+//! signing key, the Ed25519ph signature `secure-boot` checks: over the bytes
+//! the CRC covers followed by `bootloader_core::signed_fields`.
+
+use crate::elf::Segment;
+use crate::ERASED;
+use boot_protocol::{SlotHeader, HEADER_OFFSET};
+use salty::{Keypair, Sha512};
+use std::env;
+use std::fs;
+use std::mem::size_of;
+
+/// Names the file holding the raw 32-byte Ed25519 secret key, the images go
+/// unsigned without it
+pub const SIGNING_KEY_VAR: &str = "BOOT_SIGNING_KEY";
+
+/// The signing key from [`SIGNING_KEY_VAR`], `None` if it isn't set
+pub fn signing_key() -> Result<Option<Keypair>, String> {
+    let Some(path) = env::var_os(SIGNING_KEY_VAR) else {
+        return Ok(None);
+    };
+    let path = std::path::PathBuf::from(path);
+    let bytes = fs::read(&path).map_err(|e| format!("can't read {}: {e}", path.display()))?;
+    let seed: [u8; 32] = bytes.try_into().map_err(|_| {
+        format!(
+            "{} must hold a raw 32-byte Ed25519 secret key",
+            path.display()
+        )
+    })?;
+    Ok(Some(Keypair::from(&seed)))
+}
+
+/// The app image of `segments` from `base`, with its header stamped
+pub fn stamp(segments: &[Segment], base: u32, key: Option<&Keypair>) -> Result<Segment, String> {
+    let end = segments.iter().map(Segment::end).max().unwrap_or(base);
+    let len = (end - base).next_multiple_of(4);
+    let mut image = vec![ERASED; len as usize];
+    for segment in segments {
+        let offset = (segment.addr - base) as usize;
+        image[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+    }
+
+    let mut header = bootloader_core::parse_header(&image)
+        .ok_or_else(|| format!("no slot header at +{HEADER_OFFSET:#x} (slot_header! missing?)"))?;
+    header.image_len = len;
+    header.crc32 = bootloader_core::image_crc(&image, &header);
+    header.signature = match key {
+        Some(key) => {
+            let (before, after) = bootloader_core::image_parts(&image, &header);
+            let mut hash = Sha512::new();
+            hash.update(before);
+            hash.update(after);
+            hash.update(&bootloader_core::signed_fields(&header));
+            key.sign_prehashed(&hash.finalize(), None).to_bytes()
+        }
+        None => [0; 64],
+    };
+
+    let start = HEADER_OFFSET as usize;
+    image[start..start + size_of::<SlotHeader>()].copy_from_slice(&header_bytes(&header));
+    Ok(Segment {
+        addr: base,
+        data: image,
+    })
+}
+
+/// `header` as it lies in flash, `repr(C)` words then the signature
+fn header_bytes(header: &SlotHeader) -> [u8; size_of::<SlotHeader>()] {
+    let mut bytes = [0; size_of::<SlotHeader>()];
+    let words = [header.magic, header.version, header.image_len, header.crc32];
+    for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    bytes[16..].copy_from_slice(&header.signature);
+    bytes
+}